@@ -0,0 +1,250 @@
+use crate::html;
+use crate::markdown::{self, Format};
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Exit codes of the command line mode
+const EXIT_OK: i32 = 0;
+const EXIT_NOT_NORMALIZED: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+const USAGE: &str = "Usage: marko-editor [FILE]
+       marko-editor --normalize [FILE...]  rewrite files in the canonical form (stdin to stdout without files)
+       marko-editor --check FILE...        list files which are not in the canonical form, exit code 1 if any
+       marko-editor --html [FILE...]       export files as FILE.html (stdin to stdout without files)
+
+Options, they override the settings of the editor:
+       --hard-break=html|spaces|backslash
+       --collapse-blank-lines[=true|false]
+       --keep-soft-breaks[=true|false]";
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Normalize,
+    Check,
+    Html,
+}
+
+// Headless conversion: the markdown takes the same way through the document model as in the
+// editor, but GTK is not needed at all.
+pub struct Command {
+    mode: Mode,
+    files: Vec<String>,
+    options: Vec<(String, String)>, // format keys like in the settings
+}
+
+impl Command {
+    // Returns None if the arguments are meant for the GTK application.
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        let mode = match args.get(1).map(String::as_str) {
+            Some("--normalize") => Mode::Normalize,
+            Some("--check") => Mode::Check,
+            Some("--html") => Mode::Html,
+            Some("--help") | Some("-h") => {
+                println!("{}", USAGE);
+                std::process::exit(EXIT_OK);
+            }
+            _ => return None,
+        };
+        let (options, files): (Vec<_>, Vec<_>) =
+            args[2..].iter().partition(|arg| arg.starts_with("--"));
+        let options = options
+            .iter()
+            .map(|option| {
+                let (key, value) = option[2..].split_once('=').unwrap_or((&option[2..], "true"));
+                (key.replace('-', "_"), value.to_string())
+            })
+            .collect();
+        Some(Self { mode, files: files.into_iter().cloned().collect(), options })
+    }
+
+    pub fn run(&self, format: &Format) -> i32 {
+        self.run_with(format, &mut io::stdin(), &mut io::stdout())
+    }
+
+    // Without files the input is converted to the output, else the names of the changed files are
+    // listed there.
+    fn run_with(&self, format: &Format, input: &mut dyn Read, output: &mut dyn Write) -> i32 {
+        let mut format = format.clone();
+        for (key, value) in &self.options {
+            if !format.set(key, value) {
+                eprintln!("Unknown option --{}={}\n\n{}", key.replace('_', "-"), value, USAGE);
+                return EXIT_ERROR;
+            }
+        }
+        let format = &format;
+        if self.mode == Mode::Check && self.files.is_empty() {
+            eprintln!("{}", USAGE);
+            return EXIT_ERROR;
+        }
+        if self.files.is_empty() {
+            return Command::convert(&self.mode, format, input, output);
+        }
+
+        let mut result = EXIT_OK;
+        for file in &self.files {
+            let markdown = match fs::read_to_string(file) {
+                Ok(markdown) => markdown,
+                Err(e) => {
+                    eprintln!("{}: {}", file, e);
+                    result = EXIT_ERROR;
+                    continue;
+                }
+            };
+            if self.mode == Mode::Html {
+                let target = Path::new(file).with_extension("html");
+                if let Err(e) = fs::write(&target, export_html(&markdown, file, format)) {
+                    eprintln!("{}: {}", target.display(), e);
+                    result = EXIT_ERROR;
+                } else {
+                    let _ = writeln!(output, "{}", target.display());
+                }
+                continue;
+            }
+            let normalized = normalize(&markdown, format);
+            if normalized == markdown {
+                continue;
+            }
+            if self.mode == Mode::Check {
+                let _ = writeln!(output, "{}", file);
+                if result == EXIT_OK {
+                    result = EXIT_NOT_NORMALIZED;
+                }
+            } else if let Err(e) = fs::write(file, normalized) {
+                eprintln!("{}: {}", file, e);
+                result = EXIT_ERROR;
+            } else {
+                let _ = writeln!(output, "{}", file);
+            }
+        }
+        result
+    }
+
+    fn convert(mode: &Mode, format: &Format, input: &mut dyn Read, output: &mut dyn Write) -> i32 {
+        let mut markdown = String::new();
+        if let Err(e) = input.read_to_string(&mut markdown) {
+            eprintln!("stdin: {}", e);
+            return EXIT_ERROR;
+        }
+        let converted = match mode {
+            Mode::Html => export_html(&markdown, "", format),
+            _ => normalize(&markdown, format),
+        };
+        if let Err(e) = output.write_all(converted.as_bytes()) {
+            eprintln!("stdout: {}", e);
+            return EXIT_ERROR;
+        }
+        EXIT_OK
+    }
+}
+
+// The canonical form is what the editor writes after loading the markdown.
+fn normalize(markdown: &str, format: &Format) -> String {
+    markdown::serialize_with(&markdown::parse_with(markdown, format), format)
+}
+
+// The title of the page is the file name like in the editor.
+fn export_html(markdown: &str, file: &str, format: &Format) -> String {
+    let title = Path::new(file)
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().to_string());
+    html::to_html(&markdown::parse_with(markdown, format), &title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn run(command: &Command, input: &str) -> (i32, String) {
+        let mut output = vec![];
+        let result = command.run_with(&Format::default(), &mut input.as_bytes(), &mut output);
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_from_args() {
+        assert!(Command::from_args(&args(&["marko-editor"])).is_none());
+        assert!(Command::from_args(&args(&["marko-editor", "notes.md"])).is_none());
+
+        let command = Command::from_args(&args(&[
+            "marko-editor",
+            "--check",
+            "a.md",
+            "--hard-break=spaces",
+            "b.md",
+            "--keep-soft-breaks",
+        ]))
+        .unwrap();
+        assert_eq!(command.mode, Mode::Check);
+        assert_eq!(command.files, args(&["a.md", "b.md"]));
+        assert_eq!(
+            command.options,
+            vec![
+                ("hard_break".to_string(), "spaces".to_string()),
+                ("keep_soft_breaks".to_string(), "true".to_string())
+            ]
+        );
+        let command = Command::from_args(&args(&["marko-editor", "--html"])).unwrap();
+        assert_eq!(command.mode, Mode::Html);
+        assert!(command.files.is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = env::temp_dir().join(format!("marko-editor-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let normalized = dir.join("normalized.md").to_string_lossy().to_string();
+        let changed = dir.join("changed.md").to_string_lossy().to_string();
+        fs::write(&normalized, "# Notes\n\nText\n").unwrap();
+        fs::write(&changed, "Notes\n=====\nText\n").unwrap();
+
+        let check = |files: &[&str]| {
+            run(
+                &Command::from_args(&args(&[&["marko-editor", "--check"], files].concat()))
+                    .unwrap(),
+                "",
+            )
+        };
+        assert_eq!(check(&[&normalized]), (EXIT_OK, String::new()));
+        assert_eq!(
+            check(&[&normalized, &changed]),
+            (EXIT_NOT_NORMALIZED, format!("{}\n", changed))
+        );
+        let missing = dir.join("missing.md").to_string_lossy().to_string();
+        assert_eq!(check(&[&missing]).0, EXIT_ERROR);
+        assert_eq!(check(&[]).0, EXIT_ERROR);
+        // check leaves the files alone
+        assert_eq!(fs::read_to_string(&changed).unwrap(), "Notes\n=====\nText\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stdout() {
+        let normalize = Command::from_args(&args(&["marko-editor", "--normalize"])).unwrap();
+        assert_eq!(
+            run(&normalize, "Notes\n=====\n* a\n"),
+            (EXIT_OK, "# Notes\n\n* a\n".to_string())
+        );
+
+        let spaces =
+            Command::from_args(&args(&["marko-editor", "--normalize", "--hard-break=spaces"]))
+                .unwrap();
+        assert_eq!(run(&spaces, "a<br/>\nb\n"), (EXIT_OK, "a  \nb\n".to_string()));
+        let unknown =
+            Command::from_args(&args(&["marko-editor", "--normalize", "--hard-break=x"])).unwrap();
+        assert_eq!(run(&unknown, "a\n"), (EXIT_ERROR, String::new()));
+
+        let html = Command::from_args(&args(&["marko-editor", "--html"])).unwrap();
+        let (result, page) = run(&html, "*a*\n");
+        assert_eq!(result, EXIT_OK);
+        assert!(page.contains("<p><em>a</em></p>"));
+    }
+}
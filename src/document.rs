@@ -0,0 +1,348 @@
+// The document model of the markdown converter, independent of GTK.
+//
+// It mirrors the TextBuffer of the editor: plain text plus styles over char ranges.
+// Paragraph formats (headings, quotes, code blocks, tables, rules) are styles over complete lines,
+// inline formats, links, images and critic marks are styles over parts of a line.
+
+use std::ops::Range;
+
+pub const NEWLINE: &str = "\n";
+pub const NEWLINE_CHAR: char = '\n';
+
+pub const QUOTE_MAX_LEVEL: u32 = 3;
+
+// task list checkbox, the Task style is applied to one of the checkbox chars
+pub const TASK_OPEN: char = '☐';
+pub const TASK_DONE: char = '☑';
+
+// cells of a table row are separated by this char in the editor
+pub const TABLE_SEPARATOR: char = '│';
+pub const TABLE_CELL_SEPARATOR: &str = " │ ";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Style {
+    Heading(u32), // level 1 to 6
+    Quote(u32),   // nesting level from 1 to QUOTE_MAX_LEVEL
+    Code,
+    CodeLanguage(String), // info string of a fenced code block
+    Table(String),        // one char per column: '-', 'l', 'c' or 'r'
+    TableHeader,
+    Rule,
+    ListItem(u32), // on the line with the marker, nesting level from 1, the marker is text
+
+    Bold,
+    Italic,
+    Mono,
+    Strike,
+    Math,      // inline formula, written as it is between dollar signs
+    MathBlock, // display formula over complete lines
+
+    Link(String),                  // target and optional title
+    LinkReference(String, String), // label and target with optional title of the definition
+    Image(String),                 // source and optional title
+
+    FootnoteRef,
+    FootnoteDef,
+    Task,
+    Html,        // raw inline or block HTML, written back as it is
+    FrontMatter, // YAML or TOML block at the start of the file with its delimiter lines
+    HardBreak,   // on a newline inside of a paragraph
+    SoftBreak,   // on a newline of a paragraph wrapped in the file
+
+    // critic markup
+    Addition,
+    Removal,
+    Comment,
+    Highlight,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Toggle {
+    pub offset: usize,
+    pub style: Style,
+    pub on: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+// The styles are stored as toggles like in the GTK text buffer. When several styles start or end
+// at the same offset, the order of their toggles decides about the nesting in markdown, so the
+// order follows the rules of the buffer: a new style starts inside the styles, which already start
+// at the same offset, and ends outside of the styles, which already end there.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Document {
+    chars: Vec<char>,
+    toggles: Vec<Toggle>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The toggles are taken as they are, e.g. from the editor buffer.
+    pub fn with_toggles(text: &str, toggles: Vec<Toggle>) -> Self {
+        Self { chars: text.chars().collect(), toggles }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn text_range(&self, range: Range<usize>) -> String {
+        self.chars[range].iter().collect()
+    }
+
+    pub fn char_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(offset).copied()
+    }
+
+    // Like an iter in the buffer: at the start or behind a newline
+    pub fn starts_line(&self, offset: usize) -> bool {
+        offset == 0 || self.char_at(offset - 1) == Some(NEWLINE_CHAR)
+    }
+
+    pub fn line_start(&self, offset: usize) -> usize {
+        let mut start = offset.min(self.len());
+        while !self.starts_line(start) {
+            start -= 1;
+        }
+        start
+    }
+
+    pub fn line_end(&self, offset: usize) -> usize {
+        let mut end = offset;
+        while end < self.len() && self.chars[end] != NEWLINE_CHAR {
+            end += 1;
+        }
+        end
+    }
+
+    pub fn find(&self, pattern: &str, from: usize) -> Option<usize> {
+        let pattern: Vec<char> = pattern.chars().collect();
+        if pattern.is_empty() || pattern.len() > self.len() {
+            return None;
+        }
+        (from..=self.len() - pattern.len())
+            .find(|&i| self.chars[i..i + pattern.len()] == pattern[..])
+    }
+
+    pub fn toggles(&self) -> &[Toggle] {
+        &self.toggles
+    }
+
+    // The toggles are sorted by offset.
+    pub fn toggles_in(&self, range: Range<usize>) -> &[Toggle] {
+        let start = self.toggles.partition_point(|t| t.offset < range.start);
+        let end = self.toggles.partition_point(|t| t.offset < range.end);
+        &self.toggles[start..end]
+    }
+
+    // The spans are ordered to reproduce the toggles, when they are applied one after another to
+    // the plain text: of the styles starting at the same offset the inner one is applied first,
+    // of the styles ending at the same offset the outer one.
+    pub fn spans(&self) -> Vec<Span> {
+        let mut spans = vec![];
+        let mut span_of: Vec<usize> = vec![0; self.toggles.len()]; // toggle index to span index
+        for (i, toggle) in self.toggles.iter().enumerate() {
+            if toggle.on {
+                let off = self.toggles[i + 1..]
+                    .iter()
+                    .position(|t| !t.on && t.style == toggle.style)
+                    .map(|position| i + 1 + position);
+                let end = off.map_or(self.len(), |off| self.toggles[off].offset);
+                span_of[i] = spans.len();
+                if let Some(off) = off {
+                    span_of[off] = spans.len();
+                }
+                spans.push(Span { start: toggle.offset, end, style: toggle.style.clone() });
+            }
+        }
+
+        // successors[a] are the spans to be applied after span a
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; spans.len()];
+        let mut pending = vec![0; spans.len()];
+        for (i, pair) in self.toggles.windows(2).enumerate() {
+            if pair[0].offset == pair[1].offset && pair[0].on == pair[1].on {
+                let (first, second) = (span_of[i], span_of[i + 1]);
+                let (before, after) = if pair[0].on { (second, first) } else { (first, second) };
+                successors[before].push(after);
+                pending[after] += 1;
+            }
+        }
+
+        // if the order is contradictory, the remaining spans are applied as they start
+        let mut order = vec![];
+        let mut done = vec![false; spans.len()];
+        while order.len() < spans.len() {
+            let next = (0..spans.len())
+                .find(|&i| !done[i] && pending[i] == 0)
+                .or_else(|| (0..spans.len()).find(|&i| !done[i]))
+                .unwrap();
+            done[next] = true;
+            for &after in &successors[next] {
+                pending[after] -= 1;
+            }
+            order.push(spans[next].clone());
+        }
+        order
+    }
+
+    // The end of the span of the style, which is on at offset
+    pub fn span_end(&self, style: &Style, offset: usize) -> usize {
+        self.toggles_in(offset + 1..self.len() + 1)
+            .iter()
+            .find(|t| !t.on && t.style == *style)
+            .map_or(self.len(), |t| t.offset)
+    }
+
+    pub fn has_style(&self, offset: usize, style: &Style) -> bool {
+        let mut on = false;
+        for toggle in self.toggles.iter().take_while(|t| t.offset <= offset) {
+            if toggle.style == *style {
+                on = toggle.on;
+            }
+        }
+        on
+    }
+
+    pub fn styles_at(&self, offset: usize) -> Vec<Style> {
+        let mut styles: Vec<Style> = vec![];
+        for toggle in self.toggles.iter().take_while(|t| t.offset <= offset) {
+            if toggle.on {
+                styles.push(toggle.style.clone());
+            } else if let Some(index) = styles.iter().position(|s| *s == toggle.style) {
+                styles.remove(index);
+            }
+        }
+        styles
+    }
+
+    // The text is inserted behind the styles ending and in front of the styles starting at offset.
+    pub fn insert(&mut self, offset: usize, text: &str) {
+        let count = text.chars().count();
+        self.chars.splice(offset..offset, text.chars());
+        let index = self.toggle_index(offset);
+        for toggle in &mut self.toggles[index..] {
+            toggle.offset += count;
+        }
+    }
+
+    // Replaces every occurrence of the char, the styles stay as they are.
+    pub fn replace(&mut self, from: char, to: char) {
+        for c in self.chars.iter_mut().filter(|c| **c == from) {
+            *c = to;
+        }
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.insert(self.len(), text);
+    }
+
+    // The styles of the deleted text collapse at the start of the range.
+    pub fn delete(&mut self, range: Range<usize>) {
+        let count = range.len();
+        self.chars.drain(range.clone());
+        for toggle in &mut self.toggles {
+            if toggle.offset >= range.end {
+                toggle.offset -= count;
+            } else if toggle.offset > range.start {
+                toggle.offset = range.start;
+            }
+        }
+        self.remove_empty_spans();
+        self.cleanup(range.start);
+    }
+
+    pub fn apply(&mut self, style: Style, range: Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+        let mut on = self.has_style(range.start, &style);
+        if !on {
+            self.insert_toggle(Toggle { offset: range.start, style: style.clone(), on: true });
+        }
+        // toggles at the start are kept, the state at the end results from the removed ones
+        let mut i = 0;
+        while i < self.toggles.len() {
+            let toggle = &self.toggles[i];
+            if toggle.style == style && toggle.offset > range.start && toggle.offset < range.end {
+                on = !on;
+                self.toggles.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        if !on {
+            self.insert_toggle(Toggle { offset: range.end, style, on: false });
+        }
+        self.cleanup(range.start);
+        self.cleanup(range.end);
+    }
+
+    // New toggles go behind the toggles ending at the offset and in front of the starting ones.
+    fn insert_toggle(&mut self, toggle: Toggle) {
+        let index = self.toggle_index(toggle.offset);
+        self.toggles.insert(index, toggle);
+    }
+
+    // The position in front of the first style starting at offset
+    fn toggle_index(&self, offset: usize) -> usize {
+        self.toggles
+            .iter()
+            .position(|t| t.offset > offset || (t.offset == offset && t.on))
+            .unwrap_or(self.toggles.len())
+    }
+
+    // A style ending and starting again at the same offset continues.
+    fn cleanup(&mut self, offset: usize) {
+        let mut i = 0;
+        while i < self.toggles.len() {
+            let toggle = &self.toggles[i];
+            if toggle.offset == offset && !toggle.on {
+                let next = self.toggles[i + 1..]
+                    .iter()
+                    .take_while(|t| t.offset == offset)
+                    .position(|t| t.on && t.style == toggle.style);
+                if let Some(next) = next {
+                    self.toggles.remove(i + 1 + next);
+                    self.toggles.remove(i);
+                    continue;
+                }
+            }
+            i += 1;
+        }
+    }
+
+    fn remove_empty_spans(&mut self) {
+        let mut i = 0;
+        while i < self.toggles.len() {
+            let toggle = &self.toggles[i];
+            if toggle.on {
+                let next = self.toggles[i + 1..]
+                    .iter()
+                    .take_while(|t| t.offset == toggle.offset)
+                    .position(|t| !t.on && t.style == toggle.style);
+                if let Some(next) = next {
+                    self.toggles.remove(i + 1 + next);
+                    self.toggles.remove(i);
+                    continue;
+                }
+            }
+            i += 1;
+        }
+    }
+}
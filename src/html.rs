@@ -0,0 +1,543 @@
+// Export of the document model as standalone HTML page, e.g. to share notes with people, who
+// don't use the editor.
+
+use crate::document::{Document, Style, TABLE_SEPARATOR, TASK_DONE, TASK_OPEN};
+use crate::list;
+use crate::markdown::split_title;
+use crate::syntax::{self, Token};
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+// The colors and sizes follow the tags of the editor in TextTagTable.
+const STYLESHEET: &str = r#"
+body {
+    font-family: sans-serif;
+    font-size: 12pt;
+    line-height: 1.5;
+    max-width: 50em;
+    margin: 2em auto;
+    padding: 0 1em;
+}
+h1 { font-size: 24pt; }
+h2 { font-size: 22pt; }
+h3 { font-size: 20pt; }
+h4 { font-size: 18pt; }
+h5 { font-size: 16pt; }
+h6 { font-size: 14pt; }
+h1, h2, h3, h4, h5, h6 { margin: 0.5em 0 0.25em 0; }
+p { margin: 0.25em 0; }
+code, pre, table { font-family: monospace; background: rgba(0, 0, 77, 0.05); }
+pre { padding: 0.5em 30px; }
+table { border-collapse: collapse; margin: 0.5em 30px; }
+th, td { padding: 0.1em 0.6em; }
+blockquote {
+    background: rgba(77, 128, 77, 0.08);
+    color: rgb(89, 89, 89);
+    margin: 0.25em 0;
+    padding: 0.1em 0 0.1em 20px;
+}
+ins { background: rgba(0, 255, 0, 0.4); text-decoration: none; }
+del { background: rgba(255, 0, 0, 0.4); text-decoration: none; }
+mark { background: rgba(255, 255, 0, 0.6); }
+.critic-comment { background: rgba(0, 128, 255, 0.6); }
+.math { font-family: monospace; color: rgb(128, 51, 0); }
+div.math { padding: 0.5em 30px; }
+.footnote-ref a, .footnote-def { color: rgb(0, 77, 204); text-decoration: none; }
+.footnote-def { font-weight: bold; }
+.syntax-keyword { color: rgb(153, 26, 153); }
+.syntax-string { color: rgb(26, 128, 26); }
+.syntax-comment { color: rgb(128, 128, 128); font-style: italic; }
+.syntax-number { color: rgb(26, 77, 204); }
+img { max-width: 100%; }
+"#;
+
+const LIST_INDENT: usize = 4;
+
+fn escape(text: &str) -> String {
+    html_escape::encode_double_quoted_attribute(text).into_owned()
+}
+
+fn title_attribute(title: Option<&str>) -> String {
+    title.map_or(String::new(), |title| format!(" title=\"{}\"", escape(title)))
+}
+
+fn open_tag(doc: &Document, style: &Style, offset: usize) -> Option<String> {
+    let tag = match style {
+        Style::Bold => String::from("<strong>"),
+        Style::Italic => String::from("<em>"),
+        Style::Mono => String::from("<code>"),
+        // the delimiters of MathJax and KaTeX
+        Style::Math => String::from("<span class=\"math\">\\("),
+        Style::Strike => String::from("<s>"),
+        Style::Addition => String::from("<ins>"),
+        Style::Removal => String::from("<del>"),
+        Style::Highlight => {
+            // a comment directly behind the highlight is about it
+            let end = doc.span_end(style, offset);
+            match doc.toggles_in(end..end + 1).iter().find(|t| t.on && t.style == Style::Comment) {
+                Some(t) => format!(
+                    "<mark title=\"{}\">",
+                    escape(&doc.text_range(end..doc.span_end(&t.style, end)))
+                ),
+                None => String::from("<mark>"),
+            }
+        }
+        Style::Comment => String::from("<span class=\"critic-comment\">"),
+        Style::Link(link) | Style::LinkReference(_, link) => {
+            let (target, title) = split_title(link);
+            format!("<a href=\"{}\"{}>", escape(target), title_attribute(title))
+        }
+        Style::FootnoteRef => {
+            let label = escape(&doc.text_range(offset..doc.span_end(style, offset)));
+            format!("<sup class=\"footnote-ref\"><a href=\"#fn-{0}\" id=\"fnref-{0}\">", label)
+        }
+        Style::FootnoteDef => {
+            let label = escape(&doc.text_range(offset..doc.span_end(style, offset)));
+            format!("<a class=\"footnote-def\" href=\"#fnref-{0}\" id=\"fn-{0}\">", label)
+        }
+        // the checkbox char is replaced
+        Style::Task => String::new(),
+        _ => return None,
+    };
+    Some(tag)
+}
+
+fn close_tag(style: &Style) -> &'static str {
+    match style {
+        Style::Bold => "</strong>",
+        Style::Italic => "</em>",
+        Style::Mono => "</code>",
+        Style::Math => "\\)</span>",
+        Style::Strike => "</s>",
+        Style::Addition => "</ins>",
+        Style::Removal => "</del>",
+        Style::Highlight => "</mark>",
+        Style::Comment => "</span>",
+        Style::Link(_) | Style::LinkReference(..) => "</a>",
+        Style::FootnoteRef => "</a></sup>",
+        Style::FootnoteDef => "</a>",
+        _ => "",
+    }
+}
+
+// Inline formatting of a part of a line, the tags are closed at the end of the range.
+fn push_inline(html: &mut String, doc: &Document, range: Range<usize>) {
+    // open styles with their opening tag for reopening
+    let mut open: Vec<(Style, String)> = vec![];
+
+    let mut i = range.start;
+    'chars: while i < range.end {
+        // at the start of the range the styles of the text before count as well
+        let toggled: Vec<(Style, bool)> = if i == range.start {
+            doc.styles_at(i).into_iter().map(|style| (style, true)).collect()
+        } else {
+            doc.toggles_in(i..i + 1).iter().map(|t| (t.style.clone(), t.on)).collect()
+        };
+
+        for (style, _) in toggled.iter().filter(|(_, on)| !on) {
+            if let Some(index) = open.iter().rposition(|(s, _)| s == style) {
+                // the styles opened later are closed and opened again
+                let reopen = open.split_off(index + 1);
+                for (s, _) in reopen.iter().rev() {
+                    *html += close_tag(s);
+                }
+                *html += close_tag(style);
+                open.pop();
+                for (s, tag) in reopen {
+                    *html += tag.as_str();
+                    open.push((s, tag));
+                }
+            }
+        }
+        for (style, _) in toggled.into_iter().filter(|(_, on)| *on) {
+            // raw HTML goes to the page as it is
+            if style == Style::Html {
+                let end = doc.span_end(&style, i).min(range.end);
+                *html += doc.text_range(i..end).as_str();
+                i = end;
+                continue 'chars;
+            }
+            if let Style::Image(image) = &style {
+                let end = doc.span_end(&style, i).min(range.end);
+                let (source, title) = split_title(image);
+                *html += format!(
+                    "<img src=\"{}\" alt=\"{}\"{}>",
+                    escape(source),
+                    escape(&doc.text_range(i..end)),
+                    title_attribute(title)
+                )
+                .as_str();
+                i = end;
+                continue 'chars;
+            }
+            if let Some(tag) = open_tag(doc, &style, i) {
+                *html += tag.as_str();
+                open.push((style, tag));
+            }
+        }
+
+        let c = doc.char_at(i).unwrap();
+        let in_task = open.iter().any(|(s, _)| *s == Style::Task);
+        if in_task && c == TASK_OPEN {
+            *html += "<input type=\"checkbox\" disabled>";
+        } else if in_task && c == TASK_DONE {
+            *html += "<input type=\"checkbox\" checked disabled>";
+        } else if c == '\n' && doc.has_style(i, &Style::HardBreak) {
+            *html += "<br>\n";
+        } else {
+            *html += escape(c.to_string().as_str()).as_str();
+        }
+        i += 1;
+    }
+
+    for (style, _) in open.iter().rev() {
+        *html += close_tag(style);
+    }
+}
+
+fn anchor(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            ' ' | '-' | '_' => Some('-'),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[derive(Default)]
+struct Writer {
+    html: String,
+    lists: Vec<(usize, bool)>, // indentation and if the list is ordered
+    quote_level: u32,
+    anchors: HashMap<String, usize>,
+}
+
+impl Writer {
+    fn close_lists(&mut self, indent: usize) {
+        while let Some((list_indent, ordered)) = self.lists.last().copied() {
+            if list_indent < indent {
+                break;
+            }
+            self.html += if ordered { "</li>\n</ol>\n" } else { "</li>\n</ul>\n" };
+            self.lists.pop();
+        }
+    }
+
+    fn set_quote_level(&mut self, level: u32) {
+        if level != self.quote_level {
+            self.close_lists(0);
+        }
+        while self.quote_level < level {
+            self.html += "<blockquote>\n";
+            self.quote_level += 1;
+        }
+        while self.quote_level > level {
+            self.html += "</blockquote>\n";
+            self.quote_level -= 1;
+        }
+    }
+
+    // Headings get unique ids for links to them.
+    fn heading(&mut self, doc: &Document, level: u32, range: Range<usize>) {
+        let mut id = anchor(doc.text_range(range.clone()).trim());
+        let count = self.anchors.entry(id.clone()).or_insert(0);
+        if *count > 0 {
+            id = format!("{}-{}", id, count);
+        }
+        *count += 1;
+        self.html += format!("<h{} id=\"{}\">", level, escape(&id)).as_str();
+        push_inline(&mut self.html, doc, range);
+        self.html += format!("</h{}>\n", level).as_str();
+    }
+
+    fn list_item(&mut self, doc: &Document, line: Range<usize>, item: (usize, Option<u64>, usize)) {
+        let (indent, number, content) = item;
+        let ordered = number.is_some();
+        self.close_lists(indent + 1);
+        match self.lists.last().copied() {
+            Some((list_indent, list_ordered)) if list_indent == indent => {
+                if list_ordered == ordered {
+                    self.html += "</li>\n";
+                } else {
+                    self.close_lists(indent);
+                }
+            }
+            _ => {}
+        }
+        if !matches!(self.lists.last(), Some((list_indent, _)) if *list_indent >= indent) {
+            self.html += match number {
+                Some(1) | None => String::from(if ordered { "<ol>\n" } else { "<ul>\n" }),
+                Some(start) => format!("<ol start=\"{}\">\n", start),
+            }
+            .as_str();
+            self.lists.push((indent, ordered));
+        }
+        self.html += "<li>";
+        push_inline(&mut self.html, doc, line.start + content..line.end);
+    }
+
+    fn paragraph(&mut self, doc: &Document, line: Range<usize>) {
+        let text = doc.text_range(line.clone());
+        let indent = text.chars().take_while(|c| *c == ' ').count();
+        // indented paragraphs belong to the list item above
+        if indent >= LIST_INDENT && !self.lists.is_empty() {
+            self.close_lists(indent - LIST_INDENT + 1);
+        } else {
+            self.close_lists(0);
+        }
+        if text.trim().is_empty() {
+            self.html += "<p><br></p>\n";
+        } else {
+            self.html += "<p>";
+            push_inline(&mut self.html, doc, line.start + indent..line.end);
+            self.html += "</p>\n";
+        }
+    }
+
+    fn math_block(&mut self, doc: &Document, range: Range<usize>) {
+        self.html += "<div class=\"math\">\\[";
+        self.html += escape(&doc.text_range(range)).as_str();
+        self.html += "\\]</div>\n";
+    }
+
+    fn code_block(&mut self, doc: &Document, range: Range<usize>, language: Option<String>) {
+        let text = doc.text_range(range);
+        let language = language.unwrap_or_default();
+        match language.split_whitespace().next() {
+            Some(name) => {
+                self.html += format!("<pre><code class=\"language-{}\">", escape(name)).as_str()
+            }
+            None => self.html += "<pre><code>",
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let mut offset = 0;
+        for (start, end, token) in syntax::highlight(&text, &language) {
+            let class = match token {
+                Token::Keyword => "syntax-keyword",
+                Token::String => "syntax-string",
+                Token::Comment => "syntax-comment",
+                Token::Number => "syntax-number",
+            };
+            self.html += escape(&chars[offset..start].iter().collect::<String>()).as_str();
+            self.html += format!("<span class=\"{}\">", class).as_str();
+            self.html += escape(&chars[start..end].iter().collect::<String>()).as_str();
+            self.html += "</span>";
+            offset = end;
+        }
+        self.html += escape(&chars[offset..].iter().collect::<String>()).as_str();
+        self.html += "</code></pre>\n";
+    }
+
+    fn table(&mut self, doc: &Document, rows: &[Range<usize>], alignments: &str) {
+        self.html += "<table>\n";
+        for row in rows {
+            let header = doc.has_style(row.start, &Style::TableHeader);
+            let cell_tag = if header { "th" } else { "td" };
+            self.html += "<tr>";
+            let mut start = row.start;
+            for (column, cell) in doc.text_range(row.clone()).split(TABLE_SEPARATOR).enumerate() {
+                let width = cell.chars().count();
+                let leading = cell.chars().take_while(|c| c.is_whitespace()).count();
+                let trailing = cell.chars().rev().take_while(|c| c.is_whitespace()).count();
+                let align = match alignments.chars().nth(column) {
+                    Some('l') => " style=\"text-align: left\"",
+                    Some('c') => " style=\"text-align: center\"",
+                    Some('r') => " style=\"text-align: right\"",
+                    _ => "",
+                };
+                self.html += format!("<{}{}>", cell_tag, align).as_str();
+                if leading < width {
+                    push_inline(&mut self.html, doc, start + leading..start + width - trailing);
+                }
+                self.html += format!("</{}>", cell_tag).as_str();
+                start += width + 1;
+            }
+            self.html += "</tr>\n";
+        }
+        self.html += "</table>\n";
+    }
+}
+
+// A new block starts with the style, even if the block above has the same style.
+fn starts_block(doc: &Document, offset: usize, style: &Style) -> bool {
+    doc.toggles_in(offset..offset + 1).iter().any(|t| t.on && t.style == *style)
+}
+
+pub fn to_html(doc: &Document, title: &str) -> String {
+    let mut lines: Vec<Range<usize>> = vec![];
+    let mut start = 0;
+    while start < doc.len() {
+        let mut end = doc.line_end(start);
+        // line breaks inside of a paragraph don't end it
+        while end < doc.len()
+            && (doc.has_style(end, &Style::HardBreak) || doc.has_style(end, &Style::SoftBreak))
+        {
+            end = doc.line_end(end + 1);
+        }
+        lines.push(start..end);
+        start = end + 1;
+    }
+
+    let mut writer = Writer::default();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].clone();
+        let styles = doc.styles_at(line.start);
+        let level = styles
+            .iter()
+            .filter_map(|style| match style {
+                Style::Quote(level) => Some(*level),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        writer.set_quote_level(level);
+
+        let code = styles.contains(&Style::Code);
+        let math = styles.contains(&Style::MathBlock);
+        let table = styles.iter().find_map(|style| match style {
+            Style::Table(alignments) => Some(alignments.clone()),
+            _ => None,
+        });
+        if code || math || table.is_some() {
+            writer.close_lists(0);
+            let block_style = if code {
+                Style::Code
+            } else if math {
+                Style::MathBlock
+            } else {
+                Style::Table(table.clone().unwrap())
+            };
+            let mut j = i + 1;
+            while j < lines.len()
+                && doc.has_style(lines[j].start, &block_style)
+                && !starts_block(doc, lines[j].start, &block_style)
+            {
+                j += 1;
+            }
+            if let Some(alignments) = table {
+                writer.table(doc, &lines[i..j], &alignments);
+            } else if math {
+                writer.math_block(doc, line.start..lines[j - 1].end);
+            } else {
+                let language = styles.iter().find_map(|style| match style {
+                    Style::CodeLanguage(language) => Some(language.clone()),
+                    _ => None,
+                });
+                writer.code_block(doc, line.start..lines[j - 1].end, language);
+            }
+            i = j;
+            continue;
+        }
+
+        // the front matter is for site generators and not shown
+        if styles.contains(&Style::FrontMatter) {
+            i += 1;
+            continue;
+        }
+
+        let text = doc.text_range(line.clone());
+        if styles.contains(&Style::Html) && doc.span_end(&Style::Html, line.start) >= line.end {
+            // lines of an HTML block
+            writer.close_lists(0);
+            writer.html += text.as_str();
+            writer.html += "\n";
+        } else if styles.contains(&Style::Rule) {
+            writer.close_lists(0);
+            writer.html += "<hr>\n";
+        } else if let Some(level) = styles.iter().find_map(|style| match style {
+            Style::Heading(level) => Some(*level),
+            _ => None,
+        }) {
+            writer.close_lists(0);
+            writer.heading(doc, level, line);
+        } else if let Some(item) = list::item(&text) {
+            writer.list_item(doc, line, item);
+        } else {
+            writer.paragraph(doc, line);
+        }
+        i += 1;
+    }
+    writer.close_lists(0);
+    writer.set_quote_level(0);
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        STYLESHEET,
+        writer.html
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown;
+
+    fn body(markdown: &str) -> String {
+        let html = to_html(&markdown::parse(markdown), "Test");
+        let start = html.find("<body>\n").unwrap() + 7;
+        let end = html.find("</body>").unwrap();
+        html[start..end].to_string()
+    }
+
+    #[test]
+    fn test_html() {
+        assert_eq!(
+            body("# Hello *World*\n\n## Hello *World*\n"),
+            "<h1 id=\"hello-world\">Hello <em>World</em></h1>\n\
+             <h2 id=\"hello-world-1\">Hello <em>World</em></h2>\n"
+        );
+        assert_eq!(
+            body("{++add++} {--del--} {==mark==}{>>note<<} a<b\n"),
+            "<p><ins>add</ins> <del>del</del> <mark title=\"note\">mark</mark>\
+             <span class=\"critic-comment\">note</span> a&lt;b</p>\n"
+        );
+        assert_eq!(
+            body("{~~old~>new~~} {==mark==} {>>note<<}\n"),
+            "<p><del>old</del><ins>new</ins> <mark>mark</mark> \
+             <span class=\"critic-comment\">note</span></p>\n"
+        );
+        assert_eq!(
+            body("**bold *both*** [link](http://a.b \"Title\") ![alt](./i.png)\n"),
+            "<p><strong>bold <em>both</em></strong> \
+             <a href=\"http://a.b\" title=\"Title\">link</a> <img src=\"./i.png\" alt=\"alt\"></p>\n"
+        );
+        assert_eq!(
+            body("[link][ref]\n\n[ref]: http://a.b\n"),
+            "<p><a href=\"http://a.b\">link</a></p>\n"
+        );
+        assert_eq!(body("one  \ntwo\nthree\n"), "<p>one<br>\ntwo three</p>\n");
+        assert_eq!(body("---\ntitle: Notes\n---\n\nText\n"), "<p>Text</p>\n");
+        assert_eq!(
+            body("Sum $a_i<b$\n\n$$\nx_1\n$$\n"),
+            "<p>Sum <span class=\"math\">\\(a_i&lt;b\\)</span></p>\n\
+             <div class=\"math\">\\[x_1\\]</div>\n"
+        );
+        assert_eq!(
+            body("```rust\nlet x = 1;\n```\n"),
+            "<pre><code class=\"language-rust\"><span class=\"syntax-keyword\">let</span> x = \
+             <span class=\"syntax-number\">1</span>;</code></pre>\n"
+        );
+        assert_eq!(
+            body("* one\n\n    1. inner\n\n* [x] two\n\n> quote\n"),
+            "<ul>\n<li>one<ol>\n<li>inner</li>\n</ol>\n</li>\n\
+             <li><input type=\"checkbox\" checked disabled> two</li>\n</ul>\n\
+             <blockquote>\n<p>quote</p>\n</blockquote>\n"
+        );
+        assert_eq!(
+            body("| a | b |\n| - | -: |\n| **1** | 2 |\n"),
+            "<table>\n<tr><th>a</th><th style=\"text-align: right\">b</th></tr>\n\
+             <tr><td><strong>1</strong></td><td style=\"text-align: right\">2</td></tr>\n\
+             </table>\n"
+        );
+        assert_eq!(
+            body("<details>\n<summary>More</summary>\n\nH<sub>2</sub>O\n\n</details>\n"),
+            "<details>\n<summary>More</summary>\n<p>H<sub>2</sub>O</p>\n</details>\n"
+        );
+    }
+}
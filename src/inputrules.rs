@@ -0,0 +1,132 @@
+// Markdown typed in the editor is turned into the format, as soon as its syntax is complete.
+// The rules only look at the text of the line, the editor applies them to the buffer.
+
+use crate::document::Style;
+use crate::list;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum InputRule {
+    // the prefix of the line in chars is replaced by the paragraph format, e.g. "## "
+    Paragraph(usize, Style),
+    // the marker stays in the text, indentation of the item
+    ListItem(usize),
+    // the line before the typed newline is replaced
+    CodeBlock(String), // language
+    Rule,
+    // start in chars of the opening marker and the length of the markers, the closing marker
+    // ends the line
+    Inline(usize, usize, Style),
+}
+
+const INLINE: [(&str, Style); 4] =
+    [("**", Style::Bold), ("~~", Style::Strike), ("*", Style::Italic), ("`", Style::Mono)];
+
+// Only these typed chars complete markdown syntax.
+pub fn is_trigger(typed: char) -> bool {
+    matches!(typed, ' ' | '\n' | '*' | '~' | '`')
+}
+
+// The rule for the char typed at the end of line. A typed newline ends the line.
+pub fn input_rule(line: &str, typed: char) -> Option<InputRule> {
+    match typed {
+        ' ' => paragraph_rule(line),
+        '\n' => block_rule(line),
+        '*' | '~' | '`' => inline_rule(&format!("{}{}", line, typed)),
+        _ => None,
+    }
+}
+
+fn paragraph_rule(line: &str) -> Option<InputRule> {
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if hashes == line.len() && (1..=6).contains(&hashes) {
+        return Some(InputRule::Paragraph(hashes + 1, Style::Heading(hashes as u32)));
+    }
+    if line == ">" {
+        return Some(InputRule::Paragraph(2, Style::Quote(1)));
+    }
+    match list::item(&format!("{} ", line)) {
+        Some((indent, _, content)) if content == line.len() + 1 => {
+            Some(InputRule::ListItem(indent))
+        }
+        _ => None,
+    }
+}
+
+fn block_rule(line: &str) -> Option<InputRule> {
+    if line == "---" {
+        return Some(InputRule::Rule);
+    }
+    match line.strip_prefix("```") {
+        Some(language) if !language.contains('`') => {
+            Some(InputRule::CodeBlock(language.trim().to_string()))
+        }
+        _ => None,
+    }
+}
+
+// The text between the markers neither starts nor ends with a space.
+fn inline_rule(line: &str) -> Option<InputRule> {
+    let chars: Vec<char> = line.chars().collect();
+    for (marker, style) in INLINE.iter() {
+        let marker: Vec<char> = marker.chars().collect();
+        let len = marker.len();
+        // a marker char in front of the closing marker makes it a longer marker
+        if chars.len() < 2 * len + 1
+            || !chars.ends_with(&marker)
+            || chars[chars.len() - len - 1] == marker[0]
+        {
+            continue;
+        }
+        let end = chars.len() - len; // of the text
+        let last = chars[end - 1];
+        if last == ' ' {
+            return None;
+        }
+        for start in (0..end - len).rev() {
+            if chars[start..start + len] != marker[..] {
+                continue;
+            }
+            let before = if start > 0 { Some(chars[start - 1]) } else { None };
+            let first = chars[start + len];
+            if first == ' ' || before == Some(marker[0]) || before == Some('\\') {
+                return None;
+            }
+            if chars[start + len..end].contains(&marker[0]) {
+                return None;
+            }
+            return Some(InputRule::Inline(start, len, style.clone()));
+        }
+        return None;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_rule() {
+        assert_eq!(input_rule("###", ' '), Some(InputRule::Paragraph(4, Style::Heading(3))));
+        assert_eq!(input_rule("#######", ' '), None);
+        assert_eq!(input_rule("a#", ' '), None);
+        assert_eq!(input_rule(">", ' '), Some(InputRule::Paragraph(2, Style::Quote(1))));
+        assert_eq!(input_rule("    12.", ' '), Some(InputRule::ListItem(4)));
+        assert_eq!(input_rule("*", ' '), Some(InputRule::ListItem(0)));
+        assert_eq!(input_rule("text *", ' '), None);
+        assert_eq!(input_rule("---", '\n'), Some(InputRule::Rule));
+        assert_eq!(input_rule("```rust", '\n'), Some(InputRule::CodeBlock(String::from("rust"))));
+        assert_eq!(input_rule("``", '\n'), None);
+        assert_eq!(input_rule("a **bold*", '*'), Some(InputRule::Inline(2, 2, Style::Bold)));
+        assert_eq!(input_rule("**bold", '*'), None);
+        assert_eq!(input_rule("a *it", '*'), Some(InputRule::Inline(2, 1, Style::Italic)));
+        assert_eq!(input_rule("* item *x", '*'), Some(InputRule::Inline(7, 1, Style::Italic)));
+        assert_eq!(input_rule("* item", '*'), None);
+        assert_eq!(input_rule("a ~~x~", '~'), Some(InputRule::Inline(2, 2, Style::Strike)));
+        assert_eq!(input_rule("a ~x", '~'), None);
+        assert_eq!(input_rule("`code", '`'), Some(InputRule::Inline(0, 1, Style::Mono)));
+        assert_eq!(input_rule("\\`code", '`'), None);
+        assert_eq!(input_rule("a * b", '*'), None);
+        assert!(is_trigger('~') && !is_trigger('#'));
+    }
+}
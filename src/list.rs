@@ -0,0 +1,107 @@
+// The structure of lists in the text: an item is a line starting with a marker ("* " or "1. ")
+// behind the indentation of its nesting level. The marker with the number stays part of the text,
+// so the numbers of ordered lists are kept up to date here.
+
+pub const INDENT: usize = 4; // spaces per nesting level
+pub const BULLET: &str = "* ";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Line {
+    Item(usize, Option<u64>), // indentation and number of ordered items
+    Text(usize),              // indentation
+    Blank,
+}
+
+// The indentation, the number of ordered items and the start of the content in chars
+pub fn item(line: &str) -> Option<(usize, Option<u64>, usize)> {
+    let indent = line.chars().take_while(|c| *c == ' ').count();
+    let rest = &line[indent..];
+    if rest.starts_with(BULLET) {
+        return Some((indent, None, indent + BULLET.len()));
+    }
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && rest[digits..].starts_with(". ") {
+        let number = rest[..digits].parse().ok()?;
+        return Some((indent, Some(number), indent + digits + 2));
+    }
+    None
+}
+
+pub fn line(text: &str) -> Line {
+    if let Some((indent, number, _)) = item(text) {
+        Line::Item(indent, number)
+    } else if text.trim().is_empty() {
+        Line::Blank
+    } else {
+        Line::Text(text.chars().take_while(|c| *c == ' ').count())
+    }
+}
+
+// The nesting level from 1
+pub fn level(indent: usize) -> u32 {
+    (indent / INDENT) as u32 + 1
+}
+
+pub fn marker(indent: usize, number: Option<u64>) -> String {
+    match number {
+        Some(number) => format!("{}{}. ", " ".repeat(indent), number),
+        None => format!("{}{}", " ".repeat(indent), BULLET),
+    }
+}
+
+// The ordered items with a wrong number and their new number. A list keeps the number of its
+// first item, blank lines stay in the list, text ends the lists indented at least as far.
+pub fn renumber(lines: &[Line]) -> Vec<(usize, u64)> {
+    let mut changes = vec![];
+    let mut lists: Vec<(usize, bool, u64)> = vec![]; // indentation, ordered and next number
+    for (i, line) in lines.iter().enumerate() {
+        match *line {
+            Line::Blank => {}
+            Line::Text(indent) => lists.retain(|list| list.0 < indent),
+            Line::Item(indent, number) => {
+                lists.retain(|list| list.0 <= indent);
+                if matches!(lists.last(), Some(list) if list.0 == indent && list.1 != number.is_some())
+                {
+                    lists.pop();
+                }
+                match lists.last_mut() {
+                    Some(list) if list.0 == indent => {
+                        if number.is_some() && number != Some(list.2) {
+                            changes.push((i, list.2));
+                        }
+                        list.2 += 1;
+                    }
+                    _ => lists.push((indent, number.is_some(), number.unwrap_or(0) + 1)),
+                }
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item() {
+        assert_eq!(item("* text"), Some((0, None, 2)));
+        assert_eq!(item("    12. text"), Some((4, Some(12), 8)));
+        assert_eq!(item("*text"), None);
+        assert_eq!(item("1.5 text"), None);
+        assert_eq!(line("  "), Line::Blank);
+        assert_eq!(line("    text"), Line::Text(4));
+        assert_eq!(marker(4, Some(3)), "    3. ");
+        assert_eq!(level(8), 3);
+    }
+
+    #[test]
+    fn test_renumber() {
+        let lines: Vec<Line> =
+            "1. a\n1. b\n    * c\n    5. d\n    1. e\n\n7. f\n    text\n2. g\nend\n3. h"
+                .split('\n')
+                .map(line)
+                .collect();
+        assert_eq!(renumber(&lines), vec![(1, 2), (4, 6), (6, 3), (8, 4)]);
+    }
+}
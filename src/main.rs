@@ -1,11 +1,20 @@
 mod app;
+mod cli;
 mod data;
+mod document;
 mod gdk_glue;
 mod gdk_x11_glue;
 mod gtk_macros;
+mod html;
+mod inputrules;
+mod list;
 mod mainwindow;
+mod markdown;
+mod print;
 mod res;
 mod settings;
+mod source;
+mod syntax;
 mod textbufferext;
 mod textbuffermd;
 mod texttag;
@@ -18,10 +27,18 @@ mod textviewext;
 extern crate lazy_static;
 
 use crate::app::App;
+use crate::cli::Command;
+use crate::settings::Settings;
 
 use std::env::args;
 
 fn main() {
+    let args = args().collect::<Vec<_>>();
+    if let Some(command) = Command::from_args(&args) {
+        // the files are written like the editor saves them
+        std::process::exit(command.run(&Settings::read_only().markdown_format()));
+    }
+
     let app = App::new();
-    app.run(&args().collect::<Vec<_>>());
+    app.run(&args);
 }
@@ -14,13 +14,17 @@ use crate::textview::TextView;
 use crate::{builder_get, connect, connect_action_plain};
 use gtk::{FileChooserAction, FileChooserDialog, ResponseType};
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fs;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Duration;
+
+// the review list is updated, when typing pauses that long
+const REVIEW_DELAY: Duration = Duration::from_millis(500);
 
 const CSS: &str = r#"
 textview {
@@ -29,6 +33,10 @@ textview {
 menubutton {
   font-weight: bold;
 }
+button.suggest:checked {
+  background-color: @success_color;
+  color: white;
+}
 "#;
 
 struct Ui {
@@ -41,9 +49,11 @@ struct Ui {
     btn_italic: gtk::Button,
     btn_code: gtk::Button,
     btn_strike: gtk::Button,
+    btn_quote: gtk::Button,
     btn_link: gtk::Button,
     btn_undo: gtk::Button,
     btn_redo: gtk::Button,
+    btn_suggest: gtk::ToggleButton,
     btn_search: gtk::Button,
     btn_open_menu: gtk::MenuButton,
     outline_widget: gtk::Box,
@@ -52,6 +62,8 @@ struct Ui {
     outline_maxlevel: gtk::ComboBox,
     btn_outline_top: gtk::Button,
     btn_outline_bottom: gtk::Button,
+    review_expander: gtk::Expander,
+    review_view: gtk::TreeView,
     dlg_md: gtk::Dialog,
 }
 
@@ -62,6 +74,7 @@ pub struct MainWindow {
     ui: Rc<Ui>,
     css: gtk::CssProvider,
     file: Rc<RefCell<Option<PathBuf>>>,
+    review_changes: Rc<Cell<u32>>, // the buffer changes, which wait for the review update
 }
 
 impl MainWindow {
@@ -80,9 +93,11 @@ impl MainWindow {
             btn_italic: builder_get!(b("btn_italic")),
             btn_code: builder_get!(b("btn_code")),
             btn_strike: builder_get!(b("btn_strike")),
+            btn_quote: builder_get!(b("btn_quote")),
             btn_link: builder_get!(b("btn_link")),
             btn_undo: builder_get!(b("btn_undo")),
             btn_redo: builder_get!(b("btn_redo")),
+            btn_suggest: builder_get!(b("btn_suggest")),
             btn_search: builder_get!(b("btn_search")),
             btn_open_menu: builder_get!(b("btn_open_menu")),
             outline_widget: builder_get!(b("outline_widget")),
@@ -91,6 +106,8 @@ impl MainWindow {
             outline_maxlevel: builder_get!(b("outline_maxlevel")),
             btn_outline_top: builder_get!(b("btn_outline_top")),
             btn_outline_bottom: builder_get!(b("btn_outline_bottom")),
+            review_expander: builder_get!(b("review_expander")),
+            review_view: builder_get!(b("review_view")),
             dlg_md: builder_get!(b("dlg_md")),
         });
         ui.text_view_container.append(ui.text_view.get_widget());
@@ -103,6 +120,7 @@ impl MainWindow {
             ui,
             css,
             file: Rc::new(RefCell::new(None)),
+            review_changes: Rc::new(Cell::new(0)),
         };
 
         this.ui.text_view.set_activate_link_cb({
@@ -120,9 +138,14 @@ impl MainWindow {
         this.ui.btn_italic.connect_clicked(connect!(t.char_format(CharFormat::Italic)));
         this.ui.btn_code.connect_clicked(connect!(t.char_format(CharFormat::Mono)));
         this.ui.btn_strike.connect_clicked(connect!(t.char_format(CharFormat::Strike)));
+        this.ui.btn_quote.connect_clicked(connect!(t.par_format(Some(ParFormat::Quote))));
         this.ui.btn_link.connect_clicked(connect!(t.edit_link()));
         this.ui.btn_undo.connect_clicked(connect!(t.undo()));
         this.ui.btn_redo.connect_clicked(connect!(t.redo()));
+        this.ui.btn_suggest.connect_toggled({
+            let t = t.clone();
+            move |b| t.set_suggesting(b.is_active())
+        });
         this.ui.btn_search.connect_clicked(connect!(t.open_search()));
         this.ui.btn_outline_top.connect_clicked(connect!(t.scroll_to_top_bottom(true)));
         this.ui.btn_outline_bottom.connect_clicked(connect!(t.scroll_to_top_bottom(false)));
@@ -139,6 +162,22 @@ impl MainWindow {
             }
         });
 
+        this.ui.review_expander.connect_expanded_notify(connect!(this.update_review()));
+        this.ui.text_view.connect_changed(connect!(this.update_review_later()));
+        this.ui.review_view.connect_row_activated({
+            let t = this.ui.text_view.clone();
+            move |s, path, _col| {
+                let model = s.model().unwrap();
+                if let Some(iter) = model.iter(path) {
+                    let line = model.get(&iter, 3).get::<i32>().unwrap();
+                    let start = model.get(&iter, 4).get::<i32>().unwrap();
+                    let end = model.get(&iter, 5).get::<i32>().unwrap();
+                    t.scroll_to(line);
+                    t.select_range(start, end);
+                }
+            }
+        });
+
         this.ui.window.set_application(Some(app));
         this.ui.window.add_controller(&this.get_window_key_press_handler());
         this.ui.window.connect_close_request(connect!(this.close_response()));
@@ -154,11 +193,23 @@ impl MainWindow {
         this.setup_action("header_4", connect_action_plain!(t.par_format(Some(ParFormat::H4))));
         this.setup_action("header_5", connect_action_plain!(t.par_format(Some(ParFormat::H5))));
         this.setup_action("header_6", connect_action_plain!(t.par_format(Some(ParFormat::H6))));
+        this.setup_action("quote", connect_action_plain!(t.par_format(Some(ParFormat::Quote))));
+        this.setup_action("code_language", connect_action_plain!(t.edit_code_language()));
+        this.setup_action("math", connect_action_plain!(t.char_format(CharFormat::Math)));
 
         this.setup_action("green", connect_action_plain!(t.char_format(CharFormat::Green)));
         this.setup_action("red", connect_action_plain!(t.char_format(CharFormat::Red)));
         this.setup_action("yellow", connect_action_plain!(t.char_format(CharFormat::Yellow)));
         this.setup_action("blue", connect_action_plain!(t.char_format(CharFormat::Blue)));
+        this.setup_action("previous_change", connect_action_plain!(t.goto_change(true)));
+        this.setup_action("next_change", connect_action_plain!(t.goto_change(false)));
+        this.setup_action("accept_change", connect_action_plain!(t.resolve_change(true)));
+        this.setup_action("reject_change", connect_action_plain!(t.resolve_change(false)));
+        this.setup_action("accept_all_changes", connect_action_plain!(t.resolve_all_changes(true)));
+        this.setup_action(
+            "reject_all_changes",
+            connect_action_plain!(t.resolve_all_changes(false)),
+        );
 
         this.setup_action("clear_startpage", connect_action_plain!(this.act_clear_startpage()));
         this.setup_action("set_startpage", connect_action_plain!(this.act_set_startpage()));
@@ -167,6 +218,10 @@ impl MainWindow {
         this.setup_action("inspector", connect_action_plain!(this.act_inspector()));
         this.setup_action("quit", connect_action_plain!(this.close()));
         this.setup_action("save_as", connect_action_plain!(this.act_save_as()));
+        this.setup_action("export_html", connect_action_plain!(this.act_export_html()));
+        this.setup_action("export_pdf", connect_action_plain!(this.act_export_pdf()));
+        this.setup_action("print", connect_action_plain!(this.act_print()));
+        this.setup_action("front_matter", connect_action_plain!(t.toggle_front_matter()));
         this.setup_action("store_geometry", connect_action_plain!(this.store_geometry()));
 
         this.update_menu();
@@ -180,6 +235,7 @@ impl MainWindow {
     pub fn prepare_show(&self) {
         self.ui.window.realize();
         self.restore_geometry();
+        self.restore_markdown_format();
 
         let css = gtk::CssProvider::new();
         css.load_from_data(CSS.as_ref());
@@ -366,6 +422,112 @@ impl MainWindow {
         self.save_file_as(Rc::new(|_: &MainWindow| {}));
     }
 
+    // Asks for the target of an export, next to the markdown file relative paths stay valid.
+    fn export_file_as<F: Fn(&Self, &Path) + 'static>(
+        &self,
+        title: &str,
+        filter: (&str, &[&str]),
+        extension: &str,
+        export: F,
+    ) {
+        let dlg = FileChooserDialog::new(
+            Some(title),
+            Some(&self.ui.window),
+            FileChooserAction::Save,
+            &[("Export", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let export_filter = gtk::FileFilter::new();
+        export_filter.set_name(Some(filter.0));
+        for pattern in filter.1 {
+            export_filter.add_pattern(pattern);
+        }
+        dlg.add_filter(&export_filter);
+        let all_filter = gtk::FileFilter::new();
+        all_filter.set_name(Some("All files (*.*)"));
+        all_filter.add_pattern("*.*");
+        dlg.add_filter(&all_filter);
+        if let Some(filename) = self.file.borrow().deref() {
+            if let Some(name) = filename.with_extension(extension).file_name() {
+                dlg.set_current_name(name.to_str().unwrap());
+            }
+        }
+
+        dlg.connect_response({
+            let s = self.clone();
+            move |dlg: &FileChooserDialog, response: ResponseType| {
+                s.settings.store_geometry(dlg, "file_dlg_geometry");
+                if response == ResponseType::Ok {
+                    if let Some(file) = dlg.file() {
+                        let filename = file.path().expect("Couldn't get file path");
+                        export(&s, &filename);
+                    }
+                }
+                dlg.close();
+            }
+        });
+        dlg.realize();
+        self.settings.restore_geometry(&dlg, "file_dlg_geometry");
+        dlg.show();
+    }
+
+    fn act_export_html(&self) {
+        self.export_file_as(
+            "Export as HTML",
+            ("HTML (*.html, *.htm)", &["*.html", "*.htm"]),
+            "html",
+            |s: &MainWindow, filename: &Path| {
+                if fs::write(filename, s.ui.text_view.to_html(&s.document_title())).is_err() {
+                    s.show_warning(&format!(
+                        "Could not export file: {}",
+                        filename.to_str().unwrap()
+                    ));
+                }
+            },
+        );
+    }
+
+    fn act_export_pdf(&self) {
+        self.export_file_as(
+            "Export to PDF",
+            ("PDF (*.pdf)", &["*.pdf"]),
+            "pdf",
+            |s: &MainWindow, filename: &Path| {
+                let title = s.document_title();
+                if let Err(e) = s.ui.text_view.print(&s.ui.window, &title, Some(filename)) {
+                    s.show_warning(&format!("Could not export file: {}", e));
+                }
+            },
+        );
+    }
+
+    fn act_print(&self) {
+        if let Err(e) = self.ui.text_view.print(&self.ui.window, &self.document_title(), None) {
+            self.show_warning(&format!("Could not print: {}", e));
+        }
+    }
+
+    // The file name is used as title of exports and as page header on paper.
+    fn document_title(&self) -> String {
+        match self.file.borrow().deref() {
+            Some(file) => file.file_name().unwrap().to_string_lossy().to_string(),
+            None => String::from(APP_NAME),
+        }
+    }
+
+    fn show_warning(&self, text: &str) {
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.ui.window),
+            gtk::DialogFlags::MODAL
+                | gtk::DialogFlags::DESTROY_WITH_PARENT
+                | gtk::DialogFlags::USE_HEADER_BAR,
+            gtk::MessageType::Warning,
+            gtk::ButtonsType::Ok,
+            text,
+        );
+        dlg.connect_response(|d, _| d.hide());
+        dlg.show();
+    }
+
     fn write_file(&self, filename: &Path) -> std::io::Result<()> {
         let res = fs::write(filename, self.ui.text_view.to_markdown());
         if res.is_ok() {
@@ -452,6 +614,9 @@ impl MainWindow {
                         gdk::keys::constants::o => this.toggle_outline(),
                         gdk::keys::constants::p => this.toggle_dark_theme(),
                         gdk::keys::constants::s => this.btn_save_clicked(),
+                        gdk::keys::constants::u => {
+                            this.ui.btn_suggest.set_active(!this.ui.btn_suggest.is_active())
+                        }
                         _ => {}
                     }
                 }
@@ -473,6 +638,11 @@ impl MainWindow {
             "outline_visible",
             self.ui.outline_widget.get_visible().to_string().as_str(),
         );
+        self.settings.store_geometry_property(
+            &self.ui.window,
+            "review_expanded",
+            self.ui.review_expander.is_expanded().to_string().as_str(),
+        );
         let level = self.ui.outline_maxlevel.active().unwrap().to_string();
         let _ = self.settings.store("config", "outline_maxlevel", level.as_str());
     }
@@ -493,6 +663,13 @@ impl MainWindow {
                 self.ui.outline_splitter.set_position(pos);
             }
         }
+        if let Some(string) =
+            self.settings.read_geometry_property(&self.ui.window, "review_expanded")
+        {
+            if let Ok(expanded) = string.parse::<bool>() {
+                self.ui.review_expander.set_expanded(expanded);
+            }
+        }
         if let Some(string) = self.settings.get("config", "outline_maxlevel") {
             if let Ok(level) = string.parse::<u32>() {
                 self.ui.outline_maxlevel.set_active(Some(level));
@@ -500,6 +677,11 @@ impl MainWindow {
         }
     }
 
+    // The markdown style of hard breaks and empty lines is only set in the settings file.
+    fn restore_markdown_format(&self) {
+        self.ui.text_view.set_markdown_format(self.settings.markdown_format());
+    }
+
     fn close(&self) {
         self.close_file(Rc::new(|s: &MainWindow| s.ui.window.application().unwrap().quit()));
     }
@@ -632,6 +814,7 @@ impl MainWindow {
     fn toggle_outline(&self) {
         self.update_outline();
         self.ui.outline_widget.set_visible(!self.ui.outline_widget.get_visible());
+        self.update_review();
     }
 
     fn update_outline(&self) {
@@ -639,6 +822,30 @@ impl MainWindow {
         self.ui.outline_view.set_model(Some(&self.ui.text_view.get_outline_model(level)));
     }
 
+    // Rebuilding the review list on every keystroke would slow down typing in long documents.
+    fn update_review_later(&self) {
+        if !self.ui.outline_widget.get_visible() || !self.ui.review_expander.is_expanded() {
+            return;
+        }
+        let changes = self.review_changes.get().wrapping_add(1);
+        self.review_changes.set(changes);
+        let this = self.clone();
+        glib::timeout_add_local(REVIEW_DELAY, move || {
+            // only the last change of a burst updates
+            if this.review_changes.get() == changes {
+                this.update_review();
+            }
+            glib::Continue(false)
+        });
+    }
+
+    // The review list is rebuilt on changes, so only while it is shown.
+    fn update_review(&self) {
+        if self.ui.outline_widget.get_visible() && self.ui.review_expander.is_expanded() {
+            self.ui.review_view.set_model(Some(&self.ui.text_view.get_review_model()));
+        }
+    }
+
     fn toggle_dark_theme(&self) {
         if let Some(settings) = gtk::Settings::default() {
             settings.set_gtk_theme_name(Some("Adwaita"));
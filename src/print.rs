@@ -0,0 +1,377 @@
+// Printing and PDF export of the editor content. Every paragraph of the buffer gets its own pango
+// layout with the attributes of its text tags, so headings, code blocks and critic colors look
+// on paper like in the editor.
+
+use crate::texttag::{Tag, TextTagExt2};
+
+use gtk::pango;
+use gtk::prelude::*;
+use gtk::{PrintContext, PrintOperation, PrintOperationAction, TextBuffer, TextIter, TextTag};
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+const FONT: &str = "Sans 11";
+const HEADER_FONT: &str = "Sans 8";
+const HEADER_HEIGHT: f64 = 24.0; // points, includes the gap to the text
+const RULE_HEIGHT: f64 = 12.0;
+
+// Tags which are only meaningful while editing
+const SKIPPED_TAGS: [&str; 1] = [Tag::SEARCH];
+
+// A buffer line with the paragraph properties of its tags
+struct Paragraph {
+    text: String,
+    attributes: pango::AttrList,
+    alignment: pango::Alignment,
+    justify: bool,
+    left_margin: f64,
+    right_margin: f64,
+    above: f64,
+    below: f64,
+    background: Option<gdk::RGBA>,
+    rule: bool,
+}
+
+// A printed line, the positions are in points from the top of the document
+struct Line {
+    paragraph: usize,
+    top: f64,
+    height: f64,
+    y: f64, // top in the paragraph layout
+}
+
+#[derive(Default)]
+struct State {
+    layouts: Vec<pango::Layout>,
+    lines: Vec<Line>,
+    pages: Vec<usize>, // first line of the page
+}
+
+fn points(pango_units: i32) -> f64 {
+    pango_units as f64 / pango::SCALE as f64
+}
+
+fn pango_color(color: &gdk::RGBA) -> (u16, u16, u16, u16) {
+    let c = |v: f32| (v.max(0f32).min(1f32) * 65535f32) as u16;
+    (c(color.red), c(color.green), c(color.blue), c(color.alpha))
+}
+
+fn insert_attribute(
+    attributes: &pango::AttrList,
+    mut attribute: pango::Attribute,
+    bytes: (u32, u32),
+) {
+    attribute.set_start_index(bytes.0);
+    attribute.set_end_index(bytes.1);
+    attributes.insert(attribute);
+}
+
+// The character attributes of a tag, the tags are applied in the order of their priority.
+fn insert_tag_attributes(attributes: &pango::AttrList, tag: &TextTag, bytes: (u32, u32)) {
+    if tag.is_size_set() {
+        let size = (tag.size_points() * pango::SCALE as f64) as i32;
+        insert_attribute(attributes, pango::Attribute::new_size(size), bytes);
+    }
+    if tag.is_weight_set() {
+        let weight = if tag.weight() >= pango::ffi::PANGO_WEIGHT_BOLD {
+            pango::Weight::Bold
+        } else {
+            pango::Weight::Normal
+        };
+        insert_attribute(attributes, pango::Attribute::new_weight(weight), bytes);
+    }
+    if tag.is_style_set() {
+        insert_attribute(attributes, pango::Attribute::new_style(tag.style()), bytes);
+    }
+    if tag.is_family_set() {
+        if let Some(family) = tag.family() {
+            insert_attribute(attributes, pango::Attribute::new_family(&family), bytes);
+        }
+    }
+    if tag.is_strikethrough_set() {
+        let strikethrough = tag.is_strikethrough();
+        insert_attribute(attributes, pango::Attribute::new_strikethrough(strikethrough), bytes);
+    }
+    if tag.is_underline_set() {
+        insert_attribute(attributes, pango::Attribute::new_underline(tag.underline()), bytes);
+    }
+    if tag.is_rise_set() {
+        insert_attribute(attributes, pango::Attribute::new_rise(tag.rise()), bytes);
+    }
+    if tag.is_scale_set() {
+        insert_attribute(attributes, pango::Attribute::new_scale(tag.scale()), bytes);
+    }
+    if let Some(color) = tag.foreground_rgba().filter(|_| tag.is_foreground_set()) {
+        let (r, g, b, a) = pango_color(&color);
+        insert_attribute(attributes, pango::Attribute::new_foreground(r, g, b), bytes);
+        insert_attribute(attributes, pango::Attribute::new_foreground_alpha(a), bytes);
+    }
+    if let Some(color) = tag.background_rgba().filter(|_| tag.is_background_set()) {
+        let (r, g, b, a) = pango_color(&color);
+        insert_attribute(attributes, pango::Attribute::new_background(r, g, b), bytes);
+        insert_attribute(attributes, pango::Attribute::new_background_alpha(a), bytes);
+    }
+}
+
+// Hidden text isn't printed, a line without visible text is left out completely.
+fn paragraph(buffer: &TextBuffer, start: &TextIter) -> Option<Paragraph> {
+    let mut end = start.clone();
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    let text = buffer.text(start, &end, false).to_string();
+    if text.is_empty() && !buffer.text(start, &end, true).is_empty() {
+        return None;
+    }
+    let mut p = Paragraph {
+        text,
+        attributes: pango::AttrList::new(),
+        alignment: pango::Alignment::Left,
+        justify: false,
+        left_margin: 0f64,
+        right_margin: 0f64,
+        above: 0f64,
+        below: 0f64,
+        background: None,
+        rule: false,
+    };
+
+    for tag in start.tags() {
+        p.rule |= tag.get_name() == Tag::RULE;
+        if tag.is_left_margin_set() {
+            p.left_margin = p.left_margin.max(tag.left_margin() as f64);
+        }
+        if tag.is_right_margin_set() {
+            p.right_margin = p.right_margin.max(tag.right_margin() as f64);
+        }
+        if tag.is_pixels_above_lines_set() {
+            p.above = p.above.max(tag.pixels_above_lines() as f64);
+        }
+        if tag.is_pixels_below_lines_set() {
+            p.below = p.below.max(tag.pixels_below_lines() as f64);
+        }
+        if tag.is_paragraph_background_set() {
+            p.background = tag.paragraph_background_rgba();
+        }
+        if tag.is_justification_set() {
+            p.justify = tag.justification() == gtk::Justification::Fill;
+            p.alignment = match tag.justification() {
+                gtk::Justification::Center => pango::Alignment::Center,
+                gtk::Justification::Right => pango::Alignment::Right,
+                _ => pango::Alignment::Left,
+            };
+        }
+    }
+
+    // the text is black on paper, whatever the colors of the theme
+    let black = pango::Attribute::new_foreground(0, 0, 0);
+    insert_attribute(&p.attributes, black, (0, p.text.len() as u32));
+
+    let mut iter = start.clone();
+    while iter.offset() < end.offset() {
+        let mut next = iter.clone();
+        next.forward_to_tag_toggle(None::<&TextTag>);
+        if next.offset() > end.offset() {
+            next = end.clone();
+        }
+        // the byte offsets in the text without the hidden chars
+        let bytes = (iter.visible_line_index() as u32, next.visible_line_index() as u32);
+        for tag in iter.tags() {
+            if !SKIPPED_TAGS.contains(&tag.get_name().as_str()) {
+                insert_tag_attributes(&p.attributes, &tag, bytes);
+            }
+        }
+        iter = next;
+    }
+    Some(p)
+}
+
+fn paragraphs(buffer: &TextBuffer) -> Vec<Paragraph> {
+    let mut paragraphs = vec![];
+    let mut iter = buffer.start_iter();
+    loop {
+        paragraphs.extend(paragraph(buffer, &iter));
+        if !iter.forward_line() {
+            break;
+        }
+    }
+    paragraphs
+}
+
+fn begin_print(
+    op: &PrintOperation,
+    context: &PrintContext,
+    paragraphs: &[Paragraph],
+    state: &mut State,
+) {
+    let font = pango::FontDescription::from_string(FONT);
+    let body_height = context.height() - HEADER_HEIGHT;
+    let mut top = 0f64;
+    for (i, p) in paragraphs.iter().enumerate() {
+        let layout = context.create_pango_layout();
+        layout.set_font_description(Some(&font));
+        let width = context.width() - p.left_margin - p.right_margin;
+        layout.set_width((width * pango::SCALE as f64) as i32);
+        layout.set_wrap(pango::WrapMode::WordChar);
+        layout.set_alignment(p.alignment);
+        layout.set_justify(p.justify);
+        layout.set_text(&p.text);
+        layout.set_attributes(Some(&p.attributes));
+
+        top += p.above;
+        if p.rule {
+            state.lines.push(Line { paragraph: i, top, height: RULE_HEIGHT, y: 0f64 });
+            top += RULE_HEIGHT;
+        } else if let Some(mut iter) = layout.iter() {
+            loop {
+                let (_, logical) = iter.line_extents();
+                let y = points(logical.y);
+                let height = points(logical.height);
+                state.lines.push(Line { paragraph: i, top: top + y, height, y });
+                if !iter.next_line() {
+                    break;
+                }
+            }
+            top += points(layout.size().1);
+        }
+        top += p.below;
+        state.layouts.push(layout);
+    }
+
+    // page breaks between the lines
+    state.pages = vec![0];
+    let mut page_top = 0f64;
+    for (i, line) in state.lines.iter().enumerate() {
+        if line.top + line.height - page_top > body_height && *state.pages.last().unwrap() < i {
+            state.pages.push(i);
+            page_top = line.top;
+        }
+    }
+    op.set_n_pages(state.pages.len() as i32);
+}
+
+// A grey layout of the header text
+fn header_layout(context: &PrintContext, text: &str) -> pango::Layout {
+    let layout = context.create_pango_layout();
+    layout.set_font_description(Some(&pango::FontDescription::from_string(HEADER_FONT)));
+    layout.set_text(text);
+    let attributes = pango::AttrList::new();
+    let grey = pango::Attribute::new_foreground(23000, 23000, 23000);
+    insert_attribute(&attributes, grey, (0, text.len() as u32));
+    layout.set_attributes(Some(&attributes));
+    layout
+}
+
+// The layouts are drawn by gtk, the style only provides defaults overridden by the attributes.
+fn draw_header(
+    context: &PrintContext,
+    style: &gtk::StyleContext,
+    title: &str,
+    page: usize,
+    pages: usize,
+) {
+    let cr = context.cairo_context();
+
+    let layout = header_layout(context, title);
+    layout.set_width(((context.width() * 0.75) * pango::SCALE as f64) as i32);
+    layout.set_ellipsize(pango::EllipsizeMode::Start);
+    gtk::render_layout(style, &cr, 0f64, 0f64, &layout);
+
+    let layout = header_layout(context, &format!("{} / {}", page + 1, pages));
+    let width = points(layout.size().0);
+    gtk::render_layout(style, &cr, context.width() - width, 0f64, &layout);
+
+    cr.set_source_rgb(0.35, 0.35, 0.35);
+    let bottom = points(layout.size().1) + 2f64;
+    cr.set_line_width(0.5);
+    cr.move_to(0f64, bottom);
+    cr.line_to(context.width(), bottom);
+    let _ = cr.stroke();
+}
+
+fn draw_page(
+    context: &PrintContext,
+    style: &gtk::StyleContext,
+    paragraphs: &[Paragraph],
+    state: &State,
+    page: usize,
+) {
+    let cr = context.cairo_context();
+    let first = state.pages[page];
+    let last = state.pages.get(page + 1).copied().unwrap_or(state.lines.len());
+    let page_top = state.lines[first].top - HEADER_HEIGHT;
+
+    for line in &state.lines[first..last] {
+        let p = &paragraphs[line.paragraph];
+        let y = line.top - page_top;
+        if let Some(color) = &p.background {
+            cr.set_source_rgba(
+                color.red as f64,
+                color.green as f64,
+                color.blue as f64,
+                color.alpha as f64,
+            );
+            cr.rectangle(0f64, y, context.width(), line.height);
+            let _ = cr.fill();
+        }
+        if p.rule {
+            cr.set_source_rgb(0.5, 0.5, 0.5);
+            cr.set_line_width(0.5);
+            cr.move_to(0f64, y + line.height / 2f64);
+            cr.line_to(context.width(), y + line.height / 2f64);
+            let _ = cr.stroke();
+        } else {
+            // the whole paragraph is drawn, clipped to the line on this page
+            let _ = cr.save();
+            cr.rectangle(0f64, y, context.width(), line.height);
+            cr.clip();
+            let layout = &state.layouts[line.paragraph];
+            gtk::render_layout(style, &cr, p.left_margin, y - line.y, layout);
+            let _ = cr.restore();
+        }
+    }
+}
+
+// Shows the print dialog or writes the PDF file directly, if a file name is given.
+pub fn print(
+    buffer: &TextBuffer,
+    parent: &gtk::Window,
+    title: &str,
+    pdf: Option<&Path>,
+) -> Result<(), gtk::glib::Error> {
+    let op = PrintOperation::new();
+    op.set_job_name(title);
+    op.set_unit(gtk::Unit::Points);
+    op.set_embed_page_setup(true);
+
+    let paragraphs = Rc::new(paragraphs(buffer));
+    let style = parent.style_context();
+    let state = Rc::new(RefCell::new(State::default()));
+    op.connect_begin_print({
+        let paragraphs = paragraphs.clone();
+        let state = state.clone();
+        move |op, context| {
+            *state.borrow_mut() = State::default();
+            begin_print(op, context, &paragraphs, &mut state.borrow_mut());
+        }
+    });
+    op.connect_draw_page({
+        let title = title.to_string();
+        move |_, context, page| {
+            let state = state.borrow();
+            draw_header(context, &style, &title, page as usize, state.pages.len());
+            draw_page(context, &style, &paragraphs, &state, page as usize);
+        }
+    });
+
+    let action = match pdf {
+        Some(filename) => {
+            op.set_export_filename(filename);
+            PrintOperationAction::Export
+        }
+        None => PrintOperationAction::PrintDialog,
+    };
+    op.run(action, Some(parent)).map(|_| ())
+}
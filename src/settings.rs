@@ -8,6 +8,7 @@ use crate::gdk_glue::Serialize;
 #[cfg(feature = "default")]
 use crate::gdk_x11_glue::WindowGeometry;
 
+use crate::markdown::Format;
 use crate::res::APP_NAME;
 use gtk::glib;
 use gtk::glib::IsA;
@@ -25,25 +26,40 @@ pub struct Settings {
 
 impl Settings {
     pub fn new() -> Self {
-        let mut settings_file = glib::user_config_dir();
-        settings_file.push(APP_NAME);
-        fs::create_dir_all(settings_file.as_path()).unwrap_or_else(|why| {
-            println!("! {:?}", why.kind());
-        });
-        settings_file.push("settings.ini");
+        let settings_file = Settings::settings_file();
+        if let Some(dir) = settings_file.parent() {
+            fs::create_dir_all(dir).unwrap_or_else(|why| {
+                eprintln!("! {:?}", why.kind());
+            });
+        }
 
         let config = RefCell::new(Ini::new());
         if let Err(err) = config.borrow_mut().load(settings_file.as_path().to_str().unwrap()) {
-            println!("Error while reading settings: {}", err)
+            eprintln!("Error while reading settings: {}", err)
         }
 
         Self { config, settings_file }
     }
 
+    // For the command line: nothing is created and nothing is printed, which would mix with the
+    // converted markdown on stdout. Without a settings file the defaults are used.
+    pub fn read_only() -> Self {
+        let settings_file = Settings::settings_file();
+        let mut config = Ini::new();
+        let _ = config.load(settings_file.as_path().to_str().unwrap());
+        Self { config: RefCell::new(config), settings_file }
+    }
+
+    fn settings_file() -> PathBuf {
+        let mut settings_file = glib::user_config_dir();
+        settings_file.push(APP_NAME);
+        settings_file.push("settings.ini");
+        settings_file
+    }
+
     pub fn get(&self, section: &str, key: &str) -> Option<String> {
         self.config.borrow().get(section, key)
     }
-    #[allow(dead_code)]
     pub fn get_or(&self, section: &str, key: &str, default_value: &str) -> String {
         match self.config.borrow().get(section, key) {
             None => String::from(default_value),
@@ -51,6 +67,17 @@ impl Settings {
         }
     }
 
+    // The line breaks and empty lines of the markdown, the same for the editor and command line.
+    pub fn markdown_format(&self) -> Format {
+        let mut format = Format::default();
+        for key in &Format::KEYS {
+            if let Some(value) = self.get("config", key) {
+                format.set(key, &value);
+            }
+        }
+        format
+    }
+
     pub fn set(&self, section: &str, key: &str, value: &str) {
         self.config.borrow_mut().set(section, key, Some(value.parse().unwrap()));
     }
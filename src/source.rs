@@ -0,0 +1,239 @@
+// The markdown source of a loaded file. The editor writes markdown in its canonical style, but
+// the blocks, which are unchanged since loading, are written back as they were in the file. So
+// saving notes under version control changes only the edited paragraphs on disk.
+
+use crate::document::NEWLINE;
+use crate::markdown::{self, Format};
+
+use std::ops::Range;
+
+const BLOCK_SEPARATOR: &str = "\n\n";
+
+// A top level block of the file with the markdown the editor would write for it
+struct Block {
+    range: Range<usize>,
+    canonical: String,
+}
+
+pub struct Source {
+    markdown: String,
+    blocks: Vec<Block>,
+    // text between the blocks: gaps[i] is in front of blocks[i], the last one behind all blocks
+    gaps: Vec<Range<usize>>,
+    format: Format,
+}
+
+impl Source {
+    pub fn new(markdown: &str, format: &Format) -> Self {
+        let ranges = markdown::blocks(markdown);
+
+        let mut gaps = vec![];
+        let mut end = 0;
+        for range in &ranges {
+            gaps.push(end..range.start);
+            end = range.end;
+        }
+        gaps.push(end..markdown.len());
+
+        // the text between the blocks are e.g. link reference definitions, which are needed to
+        // parse a single block like the complete file
+        let mut definitions = String::new();
+        for gap in &gaps {
+            let text = markdown[gap.clone()].trim();
+            if !text.is_empty() {
+                definitions += BLOCK_SEPARATOR;
+                definitions += text;
+            }
+        }
+        let blocks = ranges
+            .into_iter()
+            .map(|range| {
+                let text = format!("{}{}{}", &markdown[range.clone()], definitions, NEWLINE);
+                let canonical =
+                    markdown::serialize_with(&markdown::parse_with(&text, format), format);
+                // without the definitions, which are written behind all blocks
+                let end = markdown::blocks(&canonical).last().map_or(0, |range| range.end);
+                Block { range, canonical: canonical[..end].to_string() }
+            })
+            .collect();
+
+        Self { markdown: markdown.to_string(), blocks, gaps, format: format.clone() }
+    }
+
+    // The canonical blocks depend on the format.
+    pub fn with_format(&self, format: &Format) -> Self {
+        Source::new(&self.markdown, format)
+    }
+
+    // Replaces the blocks of the canonical markdown, which are unchanged since loading, by their
+    // source. If that would change the content, the canonical markdown is returned.
+    pub fn restore(&self, canonical: &str) -> String {
+        let ranges = markdown::blocks(canonical);
+        let current: Vec<&str> = ranges.iter().map(|range| &canonical[range.clone()]).collect();
+        let matches = self.matching(&current);
+        if matches.iter().all(Option::is_none) {
+            return canonical.to_string();
+        }
+
+        // the link reference definitions of the file are kept, as long as they are the same
+        let definitions = &canonical[ranges.last().map_or(0, |range| range.end)..];
+        let keep_gaps = lines(definitions) == lines(&self.definitions());
+
+        let mut s = String::new();
+        let mut used_gaps = vec![!keep_gaps; self.gaps.len()];
+        for (i, block) in current.iter().enumerate() {
+            // the gaps of the file are kept next to the unchanged blocks
+            let gap = match (i.checked_sub(1).map(|i| matches[i]), matches[i]) {
+                (None, Some(0)) => Some(0),
+                (None, _) => None,
+                (Some(Some(previous)), _) => Some(previous + 1),
+                (Some(None), next) => next,
+            };
+            match gap.filter(|gap| !used_gaps[*gap]) {
+                Some(gap) => {
+                    s += &self.markdown[self.gaps[gap].clone()];
+                    used_gaps[gap] = true;
+                }
+                None if i > 0 => s += BLOCK_SEPARATOR,
+                None => {}
+            }
+            match matches[i] {
+                Some(index) => s += &self.markdown[self.blocks[index].range.clone()],
+                None => s += block,
+            }
+        }
+
+        let last = self.gaps.len() - 1;
+        let unchanged_end = keep_gaps && matches.last() == Some(&Some(self.blocks.len() - 1));
+        if unchanged_end {
+            used_gaps[last] = true;
+        }
+        // link reference definitions might be needed by the unchanged blocks
+        for (i, gap) in self.gaps.iter().enumerate() {
+            let text = self.markdown[gap.clone()].trim();
+            if !used_gaps[i] && !text.is_empty() {
+                s += BLOCK_SEPARATOR;
+                s += text;
+            }
+        }
+        if !keep_gaps {
+            s += definitions;
+        } else if unchanged_end {
+            s += &self.markdown[self.gaps[last].clone()];
+        }
+        if !s.ends_with(NEWLINE) {
+            s += NEWLINE;
+        }
+
+        if markdown::parse_with(&s, &self.format) == markdown::parse_with(canonical, &self.format) {
+            s
+        } else {
+            canonical.to_string()
+        }
+    }
+
+    fn definitions(&self) -> String {
+        let gaps = self.gaps.iter().map(|gap| &self.markdown[gap.clone()]);
+        gaps.collect::<Vec<&str>>().join(NEWLINE)
+    }
+
+    // The longest common subsequence of the current and the loaded blocks: the index of the
+    // loaded block for every unchanged current block.
+    fn matching(&self, current: &[&str]) -> Vec<Option<usize>> {
+        let (n, m) = (current.len(), self.blocks.len());
+        // lengths[i][j] is the length for current[i..] and blocks[j..]
+        let mut lengths = vec![vec![0; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = if current[i] == self.blocks[j].canonical {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+
+        let mut matches = vec![None; n];
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if current[i] == self.blocks[j].canonical {
+                matches[i] = Some(j);
+                i += 1;
+                j += 1;
+            } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        matches
+    }
+}
+
+// The non-empty lines in sorted order
+fn lines(text: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = text.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    lines.sort_unstable();
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save(original: &str, edit: impl Fn(&str) -> String) -> String {
+        let canonical = markdown::serialize(&markdown::parse(original));
+        Source::new(original, &Format::default()).restore(&edit(&canonical))
+    }
+
+    #[test]
+    fn test_restore() {
+        let original = "Title\n=====\n\n- one\n- two\n\n_emph_ and [link][ref]\n\n\n\
+                        [ref]: http://www.marko-editor.com\n\nLast __bold__\n";
+        assert_eq!(save(original, |s| s.to_string()), original);
+
+        // only the edited block is written in the canonical style
+        assert_eq!(
+            save(original, |s| s.replace("* two", "* three")),
+            "Title\n=====\n\n* one\n\n* three\n\n_emph_ and [link][ref]\n\n\n\
+             [ref]: http://www.marko-editor.com\n\nLast __bold__\n"
+        );
+        assert_eq!(
+            save(original, |s| s.replace("Last", "First")),
+            "Title\n=====\n\n- one\n- two\n\n_emph_ and [link][ref]\n\n\n\
+             [ref]: http://www.marko-editor.com\n\nFirst **bold**\n"
+        );
+
+        // the definitions are kept for the unchanged blocks
+        assert_eq!(
+            save(original, |s| s
+                .replace("Last", "Other")
+                .replace("**bold**", "**bold**\n\nNew paragraph")),
+            "Title\n=====\n\n- one\n- two\n\n_emph_ and [link][ref]\n\n\n\
+             [ref]: http://www.marko-editor.com\n\nOther **bold**\n\nNew paragraph\n"
+        );
+        assert_eq!(
+            save(original, |s| s.replace("Title", "Heading")),
+            "# Heading\n\n- one\n- two\n\n_emph_ and [link][ref]\n\n\n\
+             [ref]: http://www.marko-editor.com\n\nLast __bold__\n"
+        );
+
+        // a new reference link writes the definitions of the editor
+        assert_eq!(
+            save(original, |s| s.replace("Last", "[Last][new]").replace(
+                "[ref]: http://www.marko-editor.com",
+                "[ref]: http://www.marko-editor.com\n[new]: http://a.b"
+            )),
+            "Title\n=====\n\n- one\n- two\n\n_emph_ and [link][ref]\n\n[Last][new] **bold**\n\n\
+             [ref]: http://www.marko-editor.com\n[new]: http://a.b\n"
+        );
+
+        // the front matter is a block of its own
+        let original = "---\ntitle: Notes\n---\nFirst _one_\n";
+        assert_eq!(save(original, |s| s.to_string()), original);
+        assert_eq!(
+            save(original, |s| s.replace("one", "two")),
+            "---\ntitle: Notes\n---\nFirst *two*\n"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+// A small syntax highlighter for code blocks, the language is taken from the info string.
+// It only knows keywords, strings, comments and numbers, which is good enough for notes.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Token {
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+struct Language {
+    keywords: &'static str, // separated by whitespace
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+const RUST: Language = Language {
+    keywords: "as async await break const continue crate dyn else enum extern false fn for if \
+        impl in let loop match mod move mut pub ref return self Self static struct \
+        super trait true type unsafe use where while",
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+};
+
+const C: Language = Language {
+    keywords: "auto bool break case catch char class const constexpr continue default delete \
+        do double else enum extern false float for goto if inline int long namespace \
+        new nullptr private protected public return short signed sizeof static struct \
+        switch template this throw true try typedef typename union unsigned using \
+        virtual void volatile while",
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+};
+
+const JAVA: Language = Language {
+    keywords: "abstract boolean break byte case catch char class continue default do double \
+        else enum extends false final finally float for if implements import \
+        instanceof int interface long new null package private protected public return \
+        short static super switch this throw throws true try void while",
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+};
+
+const JAVASCRIPT: Language = Language {
+    keywords: "async await break case catch class const continue default delete do else \
+        export extends false finally for function if import in instanceof interface \
+        let new null return super switch this throw true try type typeof undefined var \
+        void while yield",
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+};
+
+const GO: Language = Language {
+    keywords: "break case chan const continue default defer else false for func go goto if \
+        import interface map nil package range return select struct switch true type \
+        var",
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '`'],
+};
+
+const PYTHON: Language = Language {
+    keywords: "and as assert async await break class continue def del elif else except False \
+        finally for from global if import in is lambda None nonlocal not or pass raise \
+        return True try while with yield",
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const SHELL: Language = Language {
+    keywords: "case do done elif else esac export fi for function if in local return then \
+        until while",
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+// Only the first word of the info string names the language, e.g. "rust ignore".
+fn language(info: &str) -> Option<&'static Language> {
+    let name = info.split_whitespace().next()?.to_lowercase();
+    match name.as_str() {
+        "rust" | "rs" => Some(&RUST),
+        "c" | "h" | "cpp" | "c++" | "cxx" | "hpp" | "cs" | "csharp" => Some(&C),
+        "java" | "kotlin" | "kt" => Some(&JAVA),
+        "javascript" | "js" | "typescript" | "ts" | "json" => Some(&JAVASCRIPT),
+        "go" | "golang" => Some(&GO),
+        "python" | "py" => Some(&PYTHON),
+        "sh" | "bash" | "shell" | "zsh" => Some(&SHELL),
+        _ => None,
+    }
+}
+
+fn starts_with_at(chars: &[char], pos: usize, pattern: &str) -> bool {
+    let mut rest = chars[pos.min(chars.len())..].iter();
+    pattern.chars().all(|p| rest.next() == Some(&p))
+}
+
+fn find_from(chars: &[char], pos: usize, pattern: &str) -> Option<usize> {
+    (pos..chars.len()).find(|&i| starts_with_at(chars, i, pattern))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Returns the tokens as (start, end, token) with char offsets into text.
+// Unknown languages get no highlighting at all.
+pub fn highlight(text: &str, info: &str) -> Vec<(usize, usize, Token)> {
+    let mut tokens = vec![];
+    let lang = match language(info) {
+        Some(lang) => lang,
+        None => return tokens,
+    };
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some((start, end)) =
+            lang.block_comment.filter(|(start, _)| starts_with_at(&chars, i, start))
+        {
+            let stop = find_from(&chars, i + start.chars().count(), end)
+                .map_or(chars.len(), |pos| pos + end.chars().count());
+            tokens.push((i, stop, Token::Comment));
+            i = stop;
+        } else if lang.line_comment.iter().any(|comment| starts_with_at(&chars, i, comment)) {
+            let stop = find_from(&chars, i, "\n").unwrap_or(chars.len());
+            tokens.push((i, stop, Token::Comment));
+            i = stop;
+        } else if lang.quotes.contains(&c) {
+            let mut stop = i + 1;
+            while stop < chars.len() && chars[stop] != c && chars[stop] != '\n' {
+                if chars[stop] == '\\' {
+                    stop += 1;
+                }
+                stop += 1;
+            }
+            stop = (stop + 1).min(chars.len());
+            tokens.push((i, stop, Token::String));
+            i = stop;
+        } else if is_word_char(c) {
+            let mut stop = i + 1;
+            while stop < chars.len() && (is_word_char(chars[stop]) || chars[stop] == '.') {
+                if chars[stop] == '.' && !c.is_ascii_digit() {
+                    break;
+                }
+                stop += 1;
+            }
+            let word: String = chars[i..stop].iter().collect();
+            if c.is_ascii_digit() {
+                tokens.push((i, stop, Token::Number));
+            } else if lang.keywords.split_whitespace().any(|keyword| keyword == word) {
+                tokens.push((i, stop, Token::Keyword));
+            }
+            i = stop;
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(highlight("fn main() {}", "text"), vec![]);
+        assert_eq!(
+            highlight("let s = \"a\\\"b\"; // 42\nx = 1.5;", "rust"),
+            vec![
+                (0, 3, Token::Keyword),
+                (8, 14, Token::String),
+                (16, 21, Token::Comment),
+                (26, 29, Token::Number),
+            ]
+        );
+        assert_eq!(
+            highlight("/* a\nb */ return x2;", "cpp extra"),
+            vec![(0, 9, Token::Comment), (10, 16, Token::Keyword)]
+        );
+        assert_eq!(
+            highlight("def f(): # 'x'", "Python"),
+            vec![(0, 3, Token::Keyword), (9, 14, Token::Comment)]
+        );
+    }
+}
@@ -1,14 +1,21 @@
+use crate::document::{Style, NEWLINE};
+use crate::list::{self, Line};
+use crate::syntax;
 use crate::texttag::{ParFormat, Tag, TextTagExt2};
 use crate::texttagtable::TextTagTable;
 
-use crate::textbuffermd::NEWLINE;
 use gtk::prelude::TextBufferExt;
 use gtk::prelude::TextTagExt;
 use gtk::TextIter;
+use regex::Regex;
 use std::path::PathBuf;
 
 pub const LINK_START: &str = "LINK:";
+pub const LINK_REFERENCE_START: &str = "LINKREF:";
 pub const IMAGE_START: &str = "IMAGE:";
+pub const TABLE_START: &str = "TABLE:";
+pub const CODE_LANGUAGE_START: &str = "LANG:";
+pub const LIST_ITEM_START: &str = "LIST:";
 
 pub fn is_file(link: &str) -> bool {
     link.starts_with("file:///")
@@ -39,10 +46,67 @@ pub trait TextBufferExt2 {
     fn get_image_at_iter(&self, iter: &gtk::TextIter) -> Option<(String, gtk::TextTag)>;
 
     fn apply_link_offset(&self, iter: &gtk::TextIter, link: &str, title: &str, start_offset: i32);
-    fn create_link_tag(&self, link: &str) -> gtk::TextTag;
+    // A reference link keeps its label, the definition is written at the end of the markdown.
+    fn create_link_tag(&self, link: &str, reference: Option<&str>) -> gtk::TextTag;
     fn get_link_at_iter(&self, iter: &gtk::TextIter) -> Option<(String, gtk::TextTag)>;
 
+    // Returns the label and the reference or definition tag of a footnote.
+    fn get_footnote_at_iter(&self, iter: &gtk::TextIter) -> Option<(String, gtk::TextTag)>;
+    fn find_tag_with_text(&self, tag: &gtk::TextTag, text: &str) -> Option<(TextIter, TextIter)>;
+
+    // The critic comment directly behind the highlight at iter.
+    fn get_highlight_comment_at_iter(&self, iter: &TextIter) -> Option<String>;
+
+    // The critic change at iter, a substitution or a highlight with its comment is one change.
+    fn get_change_at_iter(&self, iter: &TextIter) -> Option<(TextIter, TextIter)>;
+    // Accepting keeps the additions and deletes the removals, rejecting the other way round.
+    // Comments are deleted and highlights lose their color in both cases.
+    fn resolve_changes(&self, start: &TextIter, end: &TextIter, accept: bool);
+    // The start of the next or previous critic change.
+    fn find_change(&self, iter: &TextIter, backward: bool) -> Option<TextIter>;
+    // In suggestion mode text is marked as removal instead of deleted, only suggested additions
+    // are deleted. Returns the range afterwards.
+    fn mark_removal(&self, start: &TextIter, end: &TextIter) -> (TextIter, TextIter);
+
+    // The offsets of the spans of the tag between the offsets.
+    fn get_tag_ranges(&self, tag: &gtk::TextTag, start: i32, end: i32) -> Vec<(i32, i32)>;
+    // Deletes the ranges of offsets, which may overlap.
+    fn delete_ranges(&self, ranges: Vec<(i32, i32)>);
+
+    // The alignments of the columns are part of the tag name.
+    fn create_table_tag(&self, alignments: &str) -> gtk::TextTag;
+
+    // The info string of a fenced code block is kept in a tag over the whole block.
+    fn create_code_language_tag(&self, language: &str) -> gtk::TextTag;
+    fn get_code_language_at_iter(&self, iter: &TextIter) -> Option<String>;
+    fn get_code_block_bounds(&self, iter: &TextIter) -> Option<(TextIter, TextIter)>;
+    fn set_code_language(&self, language: &str, start: &TextIter, end: &TextIter);
+    fn highlight_code(&self, start: &TextIter, end: &TextIter);
+
+    // List items have a tag with their nesting level over the line with the marker.
+    fn create_list_item_tag(&self, level: u32) -> gtk::TextTag;
+    // The indentation, the number and the content start of the list item in the line.
+    fn get_list_item(&self, line: i32) -> Option<(usize, Option<u64>, usize)>;
+    // None removes the line from the list, the marker stays.
+    fn set_list_level(&self, line: i32, level: Option<u32>);
+    // Replaces the marker of the list item in the line and sets the level to its indentation.
+    fn replace_list_marker(&self, line: i32, indent: usize, number: Option<u64>);
+    // Corrects the numbers of all ordered lists.
+    fn renumber_lists(&self);
+
+    // The tag for a style of the document model, tags with dynamic names are created if needed.
+    fn get_style_tag(&self, style: &Style) -> gtk::TextTag;
+
     fn apply_paragraph_format(&self, format: Option<ParFormat>, start: &TextIter, end: &TextIter);
+    // Level 0 removes the quote.
+    fn apply_quote_level(&self, level: u32, start: &TextIter, end: &TextIter);
+    // The nesting level of the quote at iter, 0 outside of quotes.
+    fn get_quote_level_at_iter(&self, iter: &TextIter) -> u32;
+
+    // Toggles the task checkbox at iter, returns false if there is none.
+    fn toggle_task(&self, iter: &TextIter) -> bool;
+    // Toggles the task in the line or turns the line into a task list item.
+    fn toggle_task_in_line(&self, line: i32);
 
     fn get_new_mark_at(
         &self,
@@ -133,15 +197,18 @@ impl TextBufferExt2 for gtk::TextBuffer {
         let mut start = iter.clone();
         start.backward_chars(iter.offset() - start_offset);
         let tag = if title.is_empty() {
-            self.create_link_tag(link)
+            self.create_link_tag(link, None)
         } else {
-            self.create_link_tag(format!("{} \"{}\"", link, title).as_str())
+            self.create_link_tag(format!("{} \"{}\"", link, title).as_str(), None)
         };
         self.apply_tag(&tag, &start, iter);
     }
 
-    fn create_link_tag(&self, link: &str) -> gtk::TextTag {
-        let name = format!("{}{}", LINK_START, link);
+    fn create_link_tag(&self, link: &str, reference: Option<&str>) -> gtk::TextTag {
+        let name = match reference {
+            Some(label) => format!("{}[{}]{}", LINK_REFERENCE_START, label, link),
+            None => format!("{}{}", LINK_START, link),
+        };
         let is_file = is_file(link);
         let table = &self.tag_table();
         // ToDo: this lookup might be slow
@@ -159,16 +226,19 @@ impl TextBufferExt2 for gtk::TextBuffer {
     }
 
     fn get_link_at_iter(&self, iter: &TextIter) -> Option<(String, gtk::TextTag)> {
+        let link = |tag: &gtk::TextTag| {
+            tag.get_link().or_else(|| tag.get_link_reference().map(|(_, link)| link))
+        };
         let tags = iter.tags();
         for tag in tags {
-            if let Some(link) = tag.get_link() {
+            if let Some(link) = link(&tag) {
                 return Some((link, tag));
             }
         }
         // the link should also be found with the cursor at the end of the tag
         let tags = iter.toggled_tags(false);
         for tag in tags {
-            if let Some(link) = tag.get_link() {
+            if let Some(link) = link(&tag) {
                 return Some((link, tag));
             }
         }
@@ -176,6 +246,367 @@ impl TextBufferExt2 for gtk::TextBuffer {
         None
     }
 
+    fn get_footnote_at_iter(&self, iter: &TextIter) -> Option<(String, gtk::TextTag)> {
+        for name in &[Tag::FOOTNOTE_REF, Tag::FOOTNOTE_DEF] {
+            let tag = self.tag_table().lookup(name).unwrap();
+            if iter.has_tag(&tag) || iter.ends_tag(Some(&tag)) {
+                let mut start = iter.clone();
+                let mut end = iter.clone();
+                if !start.starts_tag(Some(&tag)) {
+                    start.backward_to_tag_toggle(Some(&tag));
+                }
+                if !end.ends_tag(Some(&tag)) {
+                    end.forward_to_tag_toggle(Some(&tag));
+                }
+                return Some((self.text(&start, &end, false).to_string(), tag));
+            }
+        }
+        None
+    }
+
+    fn get_highlight_comment_at_iter(&self, iter: &TextIter) -> Option<String> {
+        let highlight = self.tag_table().lookup(Tag::YELLOW).unwrap();
+        let comment = self.tag_table().lookup(Tag::BLUE).unwrap();
+        if !iter.has_tag(&highlight) {
+            return None;
+        }
+        let mut start = iter.clone();
+        start.forward_to_tag_toggle(Some(&highlight));
+        if !start.starts_tag(Some(&comment)) {
+            return None;
+        }
+        let mut end = start.clone();
+        end.forward_to_tag_toggle(Some(&comment));
+        Some(self.text(&start, &end, false).to_string())
+    }
+
+    fn get_change_at_iter(&self, iter: &TextIter) -> Option<(TextIter, TextIter)> {
+        let tag = |name: &str| self.tag_table().lookup(name).unwrap();
+        let current = Tag::CRITIC.iter().map(|name| tag(name)).find(|tag| iter.has_tag(tag))?;
+        let mut start = iter.clone();
+        let mut end = iter.clone();
+        if !start.starts_tag(Some(&current)) {
+            start.backward_to_tag_toggle(Some(&current));
+        }
+        end.forward_to_tag_toggle(Some(&current));
+
+        for (first, second) in &[(Tag::RED, Tag::GREEN), (Tag::YELLOW, Tag::BLUE)] {
+            let (first, second) = (tag(first), tag(second));
+            if current == first && end.starts_tag(Some(&second)) {
+                end.forward_to_tag_toggle(Some(&second));
+            } else if current == second && start.ends_tag(Some(&first)) {
+                start.backward_to_tag_toggle(Some(&first));
+            }
+        }
+        Some((start, end))
+    }
+
+    fn resolve_changes(&self, start: &TextIter, end: &TextIter, accept: bool) {
+        let (start, end) = (start.offset(), end.offset());
+        let mut deletions: Vec<(i32, i32)> = vec![];
+
+        self.begin_user_action();
+        for name in &Tag::CRITIC {
+            let tag = self.tag_table().lookup(name).unwrap();
+            let delete = match *name {
+                Tag::GREEN => !accept,
+                Tag::RED => accept,
+                Tag::BLUE => true,
+                _ => false,
+            };
+            for (span_start, span_end) in self.get_tag_ranges(&tag, start, end) {
+                if delete {
+                    deletions.push((span_start, span_end));
+                } else {
+                    let span_start = self.iter_at_offset(span_start);
+                    self.remove_tag(&tag, &span_start, &self.iter_at_offset(span_end));
+                }
+            }
+        }
+        self.delete_ranges(deletions);
+        self.end_user_action();
+    }
+
+    fn find_change(&self, iter: &TextIter, backward: bool) -> Option<TextIter> {
+        let mut nearest: Option<TextIter> = None;
+        for name in &Tag::CRITIC {
+            let tag = self.tag_table().lookup(name).unwrap();
+            let mut candidate = iter.clone();
+            loop {
+                let moved = if backward {
+                    candidate.backward_to_tag_toggle(Some(&tag))
+                } else {
+                    candidate.forward_to_tag_toggle(Some(&tag))
+                };
+                if !moved {
+                    break;
+                }
+                if candidate.starts_tag(Some(&tag)) {
+                    let is_nearer = match &nearest {
+                        Some(iter) if backward => candidate.offset() > iter.offset(),
+                        Some(iter) => candidate.offset() < iter.offset(),
+                        None => true,
+                    };
+                    if is_nearer {
+                        nearest = Some(candidate);
+                    }
+                    break;
+                }
+            }
+        }
+        nearest
+    }
+
+    fn mark_removal(&self, start: &TextIter, end: &TextIter) -> (TextIter, TextIter) {
+        let removal = self.tag_table().lookup(Tag::RED).unwrap();
+        let addition = self.tag_table().lookup(Tag::GREEN).unwrap();
+        let (start, end) = (start.offset(), end.offset());
+
+        self.begin_user_action();
+        self.apply_tag(&removal, &self.iter_at_offset(start), &self.iter_at_offset(end));
+        let additions = self.get_tag_ranges(&addition, start, end);
+        let deleted: i32 = additions.iter().map(|(start, end)| end - start).sum();
+        self.delete_ranges(additions);
+        self.end_user_action();
+        (self.iter_at_offset(start), self.iter_at_offset(end - deleted))
+    }
+
+    fn get_tag_ranges(&self, tag: &gtk::TextTag, start: i32, end: i32) -> Vec<(i32, i32)> {
+        let mut ranges = vec![];
+        let mut iter = self.iter_at_offset(start);
+        while iter.offset() < end {
+            if !iter.has_tag(tag) {
+                if !iter.forward_to_tag_toggle(Some(tag)) {
+                    break;
+                }
+                continue;
+            }
+            let span_start = iter.offset();
+            iter.forward_to_tag_toggle(Some(tag));
+            ranges.push((span_start, iter.offset().min(end)));
+        }
+        ranges
+    }
+
+    fn delete_ranges(&self, mut ranges: Vec<(i32, i32)>) {
+        // merged and deleted from the end, so the offsets stay valid
+        ranges.sort_unstable();
+        let mut merged: Vec<(i32, i32)> = vec![];
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        for (start, end) in merged.iter().rev() {
+            self.delete(&mut self.iter_at_offset(*start), &mut self.iter_at_offset(*end));
+        }
+    }
+
+    fn find_tag_with_text(&self, tag: &gtk::TextTag, text: &str) -> Option<(TextIter, TextIter)> {
+        let mut start = self.start_iter();
+        while start.starts_tag(Some(tag)) || start.forward_to_tag_toggle(Some(tag)) {
+            let mut end = start.clone();
+            end.forward_to_tag_toggle(Some(tag));
+            if self.text(&start, &end, false).as_str() == text {
+                return Some((start, end));
+            }
+            start = end;
+        }
+        None
+    }
+
+    fn create_table_tag(&self, alignments: &str) -> gtk::TextTag {
+        let name = format!("{}{}", TABLE_START, alignments);
+        let table = &self.tag_table();
+        if let Some(tag) = table.lookup(&name) {
+            tag
+        } else {
+            static GREY: gdk::RGBA =
+                gdk::RGBA { red: 0f32, green: 0f32, blue: 0.3f32, alpha: 0.05f32 };
+            let table_tag = TextTagTable::create_tag(&name, table);
+            table_tag.set_family(Some("Monospace"));
+            table_tag.set_paragraph_background_rgba(Some(&GREY));
+            table_tag.set_left_margin(30);
+            table_tag.set_right_margin(30);
+            table_tag.set_wrap_mode(gtk::WrapMode::None);
+            table_tag
+        }
+    }
+
+    fn create_code_language_tag(&self, language: &str) -> gtk::TextTag {
+        let name = format!("{}{}", CODE_LANGUAGE_START, language);
+        let table = &self.tag_table();
+        if let Some(tag) = table.lookup(&name) {
+            tag
+        } else {
+            TextTagTable::create_tag(&name, table)
+        }
+    }
+
+    fn get_code_language_at_iter(&self, iter: &TextIter) -> Option<String> {
+        iter.tags().iter().find_map(|tag| tag.get_code_language())
+    }
+
+    fn get_code_block_bounds(&self, iter: &TextIter) -> Option<(TextIter, TextIter)> {
+        let tag = self.tag_table().lookup(Tag::CODE).unwrap();
+        if iter.has_tag(&tag) || iter.ends_tag(Some(&tag)) {
+            let mut start = iter.clone();
+            let mut end = iter.clone();
+            if !start.starts_tag(Some(&tag)) {
+                start.backward_to_tag_toggle(Some(&tag));
+            }
+            if !end.ends_tag(Some(&tag)) {
+                end.forward_to_tag_toggle(Some(&tag));
+            }
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    fn set_code_language(&self, language: &str, start: &TextIter, end: &TextIter) {
+        self.begin_user_action();
+
+        let mut iter = start.clone();
+        while iter.offset() < end.offset() {
+            for tag in iter.tags() {
+                if tag.get_code_language().is_some() {
+                    self.remove_tag(&tag, start, end);
+                }
+            }
+            if !iter.forward_to_tag_toggle(None::<&gtk::TextTag>) {
+                break;
+            }
+        }
+        if !language.is_empty() {
+            self.apply_tag(&self.create_code_language_tag(language), start, end);
+        }
+        self.highlight_code(start, end);
+
+        self.end_user_action();
+    }
+
+    // The language at the start of the block decides, typed text might not have the tag yet.
+    fn highlight_code(&self, start: &TextIter, end: &TextIter) {
+        for name in &Tag::SYNTAX {
+            self.remove_tag_by_name(name, start, end);
+        }
+        let language = match self.get_code_language_at_iter(start) {
+            Some(language) => language,
+            None => return,
+        };
+        let text = self.text(start, end, false);
+        for (token_start, token_end, token) in syntax::highlight(text.as_str(), &language) {
+            let mut token_start_iter = start.clone();
+            token_start_iter.forward_chars(token_start as i32);
+            let mut token_end_iter = start.clone();
+            token_end_iter.forward_chars(token_end as i32);
+            self.apply_tag_by_name(Tag::from_token(&token), &token_start_iter, &token_end_iter);
+        }
+    }
+
+    fn create_list_item_tag(&self, level: u32) -> gtk::TextTag {
+        let name = format!("{}{}", LIST_ITEM_START, level);
+        let table = &self.tag_table();
+        if let Some(tag) = table.lookup(&name) {
+            tag
+        } else {
+            TextTagTable::create_tag(&name, table)
+        }
+    }
+
+    fn get_list_item(&self, line: i32) -> Option<(usize, Option<u64>, usize)> {
+        let start = self.iter_at_line(line)?;
+        if !start.tags().iter().any(|tag| tag.get_list_level().is_some()) {
+            return None;
+        }
+        let mut end = start.clone();
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        list::item(self.text(&start, &end, false).as_str())
+    }
+
+    fn set_list_level(&self, line: i32, level: Option<u32>) {
+        let start = match self.iter_at_line(line) {
+            Some(iter) => iter,
+            None => return,
+        };
+        let mut end = start.clone();
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        for tag in start.tags().iter().filter(|tag| tag.get_list_level().is_some()) {
+            self.remove_tag(tag, &start, &end);
+        }
+        if let Some(level) = level {
+            self.apply_tag(&self.create_list_item_tag(level), &start, &end);
+        }
+    }
+
+    fn replace_list_marker(&self, line: i32, indent: usize, number: Option<u64>) {
+        let (_, _, content) = match self.get_list_item(line) {
+            Some(item) => item,
+            None => return,
+        };
+        let mut start = self.iter_at_line(line).unwrap();
+        let mut end = start.clone();
+        end.forward_chars(content as i32);
+        // the marker keeps the tags of the line, e.g. of a quote
+        let tags = start.tags();
+        let offset = start.offset();
+        self.begin_user_action();
+        self.delete(&mut start, &mut end);
+        self.insert(&mut start, list::marker(indent, number).as_str());
+        for tag in &tags {
+            self.apply_tag(tag, &self.iter_at_offset(offset), &start);
+        }
+        self.set_list_level(line, Some(list::level(indent)));
+        self.end_user_action();
+    }
+
+    fn renumber_lists(&self) {
+        let mut lines = vec![];
+        for line in 0..self.line_count() {
+            let start = self.iter_at_line(line).unwrap();
+            let mut end = start.clone();
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            let text = self.text(&start, &end, false);
+            // markers without the tag are text
+            lines.push(match list::line(text.as_str()) {
+                Line::Item(indent, _) if self.get_list_item(line).is_none() => Line::Text(indent),
+                other => other,
+            });
+        }
+
+        let changes = list::renumber(&lines);
+        if changes.is_empty() {
+            return;
+        }
+        self.begin_user_action();
+        for (line, number) in changes {
+            if let Line::Item(indent, _) = lines[line] {
+                self.replace_list_marker(line as i32, indent, Some(number));
+            }
+        }
+        self.end_user_action();
+    }
+
+    fn get_style_tag(&self, style: &Style) -> gtk::TextTag {
+        match style {
+            Style::Link(link) => self.create_link_tag(link, None),
+            Style::LinkReference(label, link) => self.create_link_tag(link, Some(label)),
+            Style::Image(image) => self.create_image_tag(image),
+            Style::Table(alignments) => self.create_table_tag(alignments),
+            Style::CodeLanguage(language) => self.create_code_language_tag(language),
+            Style::ListItem(level) => self.create_list_item_tag(*level),
+            _ => self.tag_table().lookup(Tag::from_style(style).unwrap()).unwrap(),
+        }
+    }
+
     fn apply_paragraph_format(&self, format: Option<ParFormat>, start: &TextIter, end: &TextIter) {
         self.begin_user_action();
 
@@ -189,6 +620,97 @@ impl TextBufferExt2 for gtk::TextBuffer {
         self.end_user_action();
     }
 
+    fn apply_quote_level(&self, level: u32, start: &TextIter, end: &TextIter) {
+        self.begin_user_action();
+
+        // the char formats stay, only the other paragraph formats are replaced
+        let mut iter = start.clone();
+        while iter.offset() < end.offset() {
+            for tag in iter.tags().iter().filter(|tag| tag.get_par_format().is_some()) {
+                self.remove_tag(tag, start, end);
+            }
+            if !iter.forward_to_tag_toggle(None::<&gtk::TextTag>) {
+                break;
+            }
+        }
+        if level > 0 {
+            let tag = &self.tag_table().lookup(Tag::quote_tag(level)).unwrap();
+            self.apply_tag(tag, start, end);
+        }
+
+        self.end_user_action();
+    }
+
+    fn get_quote_level_at_iter(&self, iter: &TextIter) -> u32 {
+        iter.tags()
+            .iter()
+            .filter_map(|tag| Tag::quote_level(tag.get_name().as_str()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn toggle_task(&self, iter: &TextIter) -> bool {
+        let tag = self.tag_table().lookup(Tag::TASK).unwrap();
+        let c = iter.char();
+        if !iter.has_tag(&tag) || (c != Tag::TASK_OPEN && c != Tag::TASK_DONE) {
+            return false;
+        }
+
+        let mut start = iter.clone();
+        let mut end = iter.clone();
+        end.forward_char();
+        let offset = start.offset();
+        let checkbox = if c == Tag::TASK_OPEN { Tag::TASK_DONE } else { Tag::TASK_OPEN };
+
+        self.begin_user_action();
+        self.delete(&mut start, &mut end);
+        self.insert(&mut start, checkbox.to_string().as_str());
+        self.apply_tag(&tag, &self.iter_at_offset(offset), &start);
+        self.end_user_action();
+        true
+    }
+
+    fn toggle_task_in_line(&self, line: i32) {
+        let line_start = match self.iter_at_line(line) {
+            Some(iter) => iter,
+            None => return,
+        };
+        let tag = self.tag_table().lookup(Tag::TASK).unwrap();
+        let mut iter = line_start.clone();
+        while !iter.ends_line() {
+            if iter.has_tag(&tag) {
+                self.toggle_task(&iter);
+                return;
+            }
+            iter.forward_char();
+        }
+
+        // the checkbox goes behind the list marker, which is added if missing
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^\s*((\*|\d+\.)\s+)?").unwrap();
+        }
+        let text = self.text(&line_start, &iter, false);
+        let (prefix, is_item) = match RE.captures(text.as_str()) {
+            Some(caps) => (caps.get(0).unwrap().as_str().chars().count(), caps.get(1).is_some()),
+            None => (0, false),
+        };
+        let mut insert = line_start.clone();
+        insert.forward_chars(prefix as i32);
+
+        self.begin_user_action();
+        if !is_item {
+            self.insert(&mut insert, list::BULLET);
+        }
+        let offset = insert.offset();
+        self.insert(&mut insert, format!("{} ", Tag::TASK_OPEN).as_str());
+        let mut end = self.iter_at_offset(offset);
+        end.forward_char();
+        self.apply_tag(&tag, &self.iter_at_offset(offset), &end);
+        let indent = text.chars().take_while(|c| *c == ' ').count();
+        self.set_list_level(line, Some(list::level(indent)));
+        self.end_user_action();
+    }
+
     fn get_new_mark_at(
         &self,
         name: Option<&str>,
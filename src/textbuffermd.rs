@@ -1,35 +1,28 @@
+use crate::document::{Document, Style, Toggle, NEWLINE};
+use crate::markdown::{self, Format};
 use crate::textbufferext::TextBufferExt2;
-use crate::texttag::{Tag, TextTagExt2};
-use crate::texttagtable::TextTagTable;
+use crate::texttag::TextTagExt2;
 use gtk::prelude::TextBufferExt;
-use pulldown_cmark::{CodeBlockKind, Event, Options, Parser};
-
-type CTag<'a> = pulldown_cmark::Tag<'a>;
-
-// Todo: Check if the newline handling work on other platforms as expected (e.g. Windows)
-pub const NEWLINE: &str = "\n";
-const NEWLINE_CHAR: char = '\n';
-const BREAK: &str = "<br/>";
-const BREAK_NEWLINE: &str = "<br/>\n";
-// ToDo: escaping is far from complete
-const ESCAPES_EVERYWHERE: [char; 2] = ['`', '_'];
-const ESCAPES_ONLY_IN_BLOCK: [char; 1] = ['*'];
 
+// Maps the document model of the markdown converter to the tags of the buffer and back.
 pub trait TextBufferMd {
-    fn to_markdown(&self) -> String;
-    fn insert_markdown(&self, iter: &mut gtk::TextIter, markdown: &str);
+    fn to_markdown(&self, format: &Format) -> String;
+    fn insert_markdown(&self, iter: &mut gtk::TextIter, markdown: &str, format: &Format);
 
-    fn assign_markup(&self, markup: &str) -> &gtk::TextBuffer;
-    fn assign_markdown(&self, markdown: &str, buffer_is_modified: bool) -> &gtk::TextBuffer;
-    fn apply_tag_offset(&self, iter: &mut gtk::TextIter, tag_name: &str, start_offset: i32);
-    // ToDo: duplicated code for image and link
-    fn apply_image_offset(&self, iter: &gtk::TextIter, image: &str, title: &str, start_offset: i32);
+    fn to_document(&self) -> Document;
+    fn insert_document(&self, iter: &mut gtk::TextIter, doc: &Document);
 
-    fn convert_colors(&self, tag: &str, pos_start: i32);
+    fn assign_markup(&self, markup: &str) -> &gtk::TextBuffer;
+    fn assign_markdown(
+        &self,
+        markdown: &str,
+        format: &Format,
+        buffer_is_modified: bool,
+    ) -> &gtk::TextBuffer;
 }
 
 impl TextBufferMd for gtk::TextBuffer {
-    fn to_markdown(&self) -> String {
+    fn to_markdown(&self, format: &Format) -> String {
         // add newline at end if needed
         let mut end = self.end_iter();
         let mut start = end.clone();
@@ -37,366 +30,59 @@ impl TextBufferMd for gtk::TextBuffer {
             self.insert(&mut end, NEWLINE);
         }
 
-        // resulting string
-        let mut s = String::new();
-
-        // currently open tags
-        let mut open: Vec<String> = vec![];
-        // next_open catches unnecessary open tags from the overflow list - they get only written,
-        // if the next char is not the corresponding closing tag.
-        let mut next_open: Vec<&str> = vec![];
-
-        let mut newline_count = 0; // empty consecutive newlines in the editor
-        let mut has_image = false;
-        let mut has_link = false;
-        let mut in_code_block = false;
-        let mut formatted = true;
-        let mut is_start_of_line = true; // after newlines and possible white space
-
-        let mut it = self.start_iter();
-        let mut c = it.char();
-        while c != char::from(0) {
-            // newline handling
-            if c == NEWLINE_CHAR {
-                newline_count += 1;
-                if newline_count > 1 && !in_code_block {
-                    if newline_count > 2 {
-                        s += NEWLINE;
-                    }
-                    s += BREAK;
-                    it.forward_char();
-                    c = it.char();
-                    continue;
-                }
-            } else {
-                if newline_count > 1 && !in_code_block {
-                    s += NEWLINE;
-                    s += NEWLINE;
-                }
-                if newline_count > 0 {
-                    is_start_of_line = true;
-                }
-                newline_count = 0;
-            }
-
-            // closing tags before new opening tags
-            let off_tags = it.toggled_tags(false);
-            if has_image {
-                for tag in &off_tags {
-                    if let Some(image) = tag.get_image() {
-                        has_image = false;
-                        s += format!("]({})", image.as_str()).as_ref();
-                        continue;
-                    }
-                }
-            }
-            if has_link {
-                for tag in &off_tags {
-                    if let Some(link) = tag.get_link() {
-                        has_link = false;
-                        s += format!("]({})", link.as_str()).as_ref();
-                        continue;
-                    }
-                }
-            }
-            for tag in off_tags.iter().rev() {
-                // reverse to keep multiple tags in order
-                let name = tag.get_name();
-                if name.eq(Tag::CODE) {
-                    in_code_block = false;
-                    formatted = true;
-                } else if name.eq(Tag::MONO) {
-                    formatted = true;
-                }
-                if TextTagTable::md_end_tag(name.as_str()).is_some() {
-                    let mut overflow: Vec<String> = vec![];
-                    let mut top = open.pop();
-                    while let Some(top_name) = top.clone() {
-                        let matching_start = TextTagTable::md_start_tag(top_name.as_str()).unwrap();
-                        if let Some(index) = next_open.iter().position(|i| i.eq(&matching_start)) {
-                            next_open.remove(index);
-                            if let Some(index) = open.iter().position(|i| i.eq(&top_name)) {
-                                open.remove(index);
-                            } else {
-                                // the candidate was top, which is already removed from open
-                                // ToDo: this assert fails for "{++{==Hallo **Welt!**==}++}\n"
-                                //assert_eq!(name, top_name);
-                            }
-                        } else {
-                            // it should be ok, to not write out the remaining open tags here
-                            s += TextTagTable::md_end_tag(top_name.as_str()).unwrap();
-                        }
-                        next_open.clear();
-                        if top_name.ne(&name) {
-                            overflow.push(top_name);
-                            top = open.pop();
-                        } else {
-                            break;
-                        }
-                    }
-                    for value in overflow.iter().rev() {
-                        next_open.push(TextTagTable::md_start_tag(value).unwrap());
-                        open.push(value.clone());
-                    }
-                }
-            }
+        markdown::serialize_with(&self.to_document(), format)
+    }
 
-            let on_tags = it.toggled_tags(true);
-            let mut handle_image = false;
-            let mut handle_link = false;
-            // check first if we enter an unformatted block
-            let mut stop_formatting_here = false;
-            for tag in on_tags.iter().rev() {
-                // reverse to keep multiple tags in order
-                let name = tag.get_name();
-                if name.eq(Tag::CODE) {
-                    in_code_block = true;
-                    stop_formatting_here = formatted;
-                    formatted = false;
-                    continue;
-                } else if name.eq(Tag::MONO) {
-                    stop_formatting_here = formatted;
-                    formatted = false;
-                    continue;
-                }
-            }
-            if stop_formatting_here {
-                // ToDo: close all open tags!
-            }
+    fn insert_markdown(&self, iter: &mut gtk::TextIter, markdown: &str, format: &Format) {
+        self.insert_document(iter, &markdown::parse_with(markdown, format));
+    }
 
-            // reverse loop to keep multiple tags in order
-            for tag in on_tags.iter().rev() {
-                let name = tag.get_name();
-                if formatted || name.eq(Tag::MONO) || name.eq(Tag::CODE) {
-                    if let Some(diff) = TextTagTable::md_start_tag(name.as_str()) {
-                        open.push(name);
-                        next_open.push(diff);
-                    } else if tag.get_image().is_some() {
-                        has_image = true;
-                        handle_image = true;
-                    } else if tag.get_link().is_some() {
-                        has_link = true;
-                        handle_link = true;
-                    }
+    fn to_document(&self) -> Document {
+        let mut text = String::new();
+        let mut toggles: Vec<Toggle> = vec![];
+        let mut offset = 0;
+
+        let mut iter = self.start_iter();
+        loop {
+            // closing tags before opening tags, reverse to keep multiple tags in order
+            let off_tags = iter.toggled_tags(false);
+            let on_tags = iter.toggled_tags(true);
+            let tags = off_tags.iter().rev().map(|tag| (tag, false));
+            for (tag, on) in tags.chain(on_tags.iter().rev().map(|tag| (tag, true))) {
+                if let Some(style) = tag.get_style() {
+                    toggles.push(Toggle { offset, style, on });
                 }
             }
 
-            for t in next_open.drain(..) {
-                s += t;
-            }
-            if handle_image {
-                s += "![";
-            }
-            if handle_link {
-                s += "[";
-            }
-
-            // newlines in regular lines the editor become paragraphs in markdown
-            if c == NEWLINE_CHAR && !in_code_block {
-                s += NEWLINE;
-            }
-            if formatted
-                && (ESCAPES_EVERYWHERE.contains(&c)
-                    || (!is_start_of_line) && ESCAPES_ONLY_IN_BLOCK.contains(&c))
-            {
-                s.push('\\');
-            }
-            s.push(c);
-
-            is_start_of_line = is_start_of_line && c.is_whitespace();
-
-            it.forward_char();
-            c = it.char();
-        }
-
-        // close all open tags
-        for t in next_open.drain(..) {
-            s += t;
-        }
-        if !open.is_empty() {
-            while s.ends_with(NEWLINE) {
-                s.pop();
-            }
-            for value in open.iter().rev() {
-                s += TextTagTable::md_end_tag(value).unwrap();
+            let start = iter.clone();
+            let found = iter.forward_to_tag_toggle(None::<&gtk::TextTag>);
+            let part = self.text(&start, &iter, false);
+            offset += part.chars().count();
+            text += part.as_str();
+            if !found {
+                break;
             }
         }
 
-        // exactly one newline at the end
-        while s.ends_with(NEWLINE) {
-            s.pop();
-        }
-        s += NEWLINE;
-
-        s
+        Document::with_toggles(text.as_str(), toggles)
     }
 
-    fn insert_markdown(&self, iter: &mut gtk::TextIter, markdown: &str) {
+    fn insert_document(&self, iter: &mut gtk::TextIter, doc: &Document) {
+        if doc.is_empty() {
+            return;
+        }
         let pos_start = iter.offset();
-
-        let mut options = Options::empty();
-        options.insert(Options::ENABLE_STRIKETHROUGH);
-        let parser = Parser::new_ext(markdown, options);
-
-        let mut pos_heading = 0;
-        let mut pos_image = 0;
-        let mut pos_link = 0;
-        let mut pos_bold = 0;
-        let mut pos_italic = 0;
-        let mut pos_mono = 0;
-        let mut pos_strike = 0;
-
-        let mut list_ident = 0;
-        let mut list_number: Vec<Option<u64>> = vec![];
-        let mut list_item_empty = false; // needed for paragraphs in lists
-
-        for event in parser {
-            //println!("\nEvent:{:?}", &event);
-            match event {
-                Event::Start(tag) => match tag {
-                    CTag::Heading(_) => pos_heading = iter.offset(),
-                    CTag::Paragraph => {
-                        if !iter.starts_line() && (list_ident == 0 || !list_item_empty) {
-                            self.insert(iter, NEWLINE);
-                            list_item_empty = false;
-                        }
-                        if list_ident > 0 && !list_item_empty {
-                            self.insert(iter, "    ".repeat(list_ident).as_str());
-                        }
-                    }
-                    CTag::Image(..) => pos_image = iter.offset(),
-                    CTag::Link(..) => pos_link = iter.offset(),
-                    CTag::List(number) => {
-                        list_number.push(number);
-                        // a sublist comes before the end tag
-                        if !iter.starts_line() {
-                            self.insert(iter, NEWLINE);
-                        }
-                        list_ident += 1;
-                    }
-                    CTag::Item => {
-                        list_item_empty = true;
-                        self.insert(
-                            iter,
-                            format!(
-                                "{}{} ",
-                                "    ".repeat(list_ident - 1),
-                                if let Some(Some(i)) = list_number.last_mut() {
-                                    let r = i.to_string() + ".";
-                                    *i += 1;
-                                    r
-                                } else {
-                                    String::from("*")
-                                },
-                            )
-                            .as_str(),
-                        )
-                    }
-                    CTag::Strong => pos_bold = iter.offset(),
-                    CTag::Emphasis => pos_italic = iter.offset(),
-                    CTag::CodeBlock(kind) => match kind {
-                        CodeBlockKind::Indented => pos_mono = iter.offset(),
-                        CodeBlockKind::Fenced(_) => pos_mono = iter.offset(),
-                    },
-                    CTag::Strikethrough => pos_strike = iter.offset(),
-                    _ => {} //println!("\nStart tag: {:?}", &tag),
-                },
-                Event::End(tag) => match tag {
-                    CTag::Heading(level) => {
-                        let tag = match level {
-                            1 => Tag::H1,
-                            2 => Tag::H2,
-                            3 => Tag::H3,
-                            4 => Tag::H4,
-                            5 => Tag::H5,
-                            6 => Tag::H6,
-                            _ => continue,
-                        };
-                        self.apply_tag_offset(iter, tag, pos_heading);
-                        self.insert(iter, NEWLINE);
-                    }
-                    CTag::Paragraph => {
-                        // paragraphs in lists already have a newline
-                        if !iter.starts_line() {
-                            self.insert(iter, NEWLINE);
-                        }
-                    }
-                    CTag::Image(_, image, title) => {
-                        self.apply_image_offset(iter, image.as_ref(), title.as_ref(), pos_image)
-                    }
-                    CTag::Link(_, link, title) => {
-                        self.apply_link_offset(iter, link.as_ref(), title.as_ref(), pos_link)
-                    }
-                    CTag::List(_) => {
-                        list_ident -= 1;
-                        list_number.pop();
-                    }
-                    CTag::Item => {
-                        // a sublist comes before the end tag
-                        // also sublists close directly after one another
-                        if !iter.starts_line() {
-                            self.insert(iter, NEWLINE);
-                        }
-                    }
-                    CTag::Strong => self.apply_tag_offset(iter, Tag::BOLD, pos_bold),
-                    CTag::Emphasis => self.apply_tag_offset(iter, Tag::ITALIC, pos_italic),
-                    CTag::CodeBlock(kind) => match kind {
-                        CodeBlockKind::Indented => self.apply_tag_offset(iter, Tag::MONO, pos_mono),
-                        CodeBlockKind::Fenced(_) => {
-                            self.apply_tag_offset(iter, Tag::CODE, pos_mono)
-                        }
-                    },
-                    CTag::Strikethrough => self.apply_tag_offset(iter, Tag::STRIKE, pos_strike),
-                    _ => {} //println!("\nEnd tag: {:?}", &tag),
-                },
-                Event::Text(text) => {
-                    self.insert(iter, text.as_ref());
-                    list_item_empty = false;
-                }
-                Event::Code(text) => {
-                    pos_mono = iter.offset();
-                    self.insert(iter, text.as_ref());
-                    self.apply_tag_offset(iter, Tag::MONO, pos_mono);
-                }
-                Event::Html(html) => {
-                    // special newline handling
-                    let str = html.as_ref();
-                    if str.eq(BREAK_NEWLINE) {
-                        self.insert(iter, NEWLINE);
-                    } else {
-                        self.insert(iter, str);
-                    }
-                }
-                // Event::SoftBreak => self.insert(iter, NEWLINE),
-                Event::HardBreak => self.insert(iter, NEWLINE),
-                Event::Rule => {
-                    let pos_rule = iter.offset();
-                    self.insert(iter, format!("{}{}", Tag::MD_RULE, NEWLINE).as_str());
-                    self.apply_tag_offset(iter, Tag::RULE, pos_rule);
-                }
-                // FootnoteReference(name) => {
-                //     let len = self.numbers.len() + 1;
-                //     self.write("<sup class=\"footnote-reference\"><a href=\"#")?;
-                //     escape_html(&mut self.writer, &name)?;
-                //     self.write("\">")?;
-                //     let number = *self.numbers.entry(name).or_insert(len);
-                //     write!(&mut self.writer, "{}", number)?;
-                //     self.write("</a></sup>")?;
-                // }
-                // TaskListMarker(true) => {
-                //     self.write("<input disabled=\"\" type=\"checkbox\" checked=\"\"/>\n")?;
-                // }
-                // TaskListMarker(false) => {
-                //     self.write("<input disabled=\"\" type=\"checkbox\"/>\n")?;
-                // }
-                _ => {} //println!("\nEvent:{:?}", &event),
+        self.insert(iter, doc.text().as_str());
+
+        for span in doc.spans() {
+            let start = self.iter_at_offset(pos_start + span.start as i32);
+            let end = self.iter_at_offset(pos_start + span.end as i32);
+            self.apply_tag(&self.get_style_tag(&span.style), &start, &end);
+            if let Style::CodeLanguage(_) = span.style {
+                self.highlight_code(&start, &end);
             }
         }
-
-        self.convert_colors(Tag::GREEN, pos_start);
-        self.convert_colors(Tag::RED, pos_start);
-        self.convert_colors(Tag::BLUE, pos_start);
-        self.convert_colors(Tag::YELLOW, pos_start);
+        *iter = self.iter_at_offset(pos_start + doc.len() as i32);
     }
 
     fn assign_markup(&self, markup: &str) -> &gtk::TextBuffer {
@@ -405,79 +91,23 @@ impl TextBufferMd for gtk::TextBuffer {
         self
     }
 
-    fn assign_markdown(&self, markdown: &str, buffer_is_modified: bool) -> &gtk::TextBuffer {
+    fn assign_markdown(
+        &self,
+        markdown: &str,
+        format: &Format,
+        buffer_is_modified: bool,
+    ) -> &gtk::TextBuffer {
         self.delete(&mut self.start_iter(), &mut self.end_iter());
-        self.insert_markdown(&mut self.start_iter(), markdown);
+        self.insert_markdown(&mut self.start_iter(), markdown, format);
         self.set_modified(buffer_is_modified);
         self
     }
-
-    fn apply_tag_offset(&self, iter: &mut gtk::TextIter, tag_name: &str, start_offset: i32) {
-        let mut start = iter.clone();
-        start.backward_chars(iter.offset() - start_offset);
-        // formatting should not be applied to the last newline (if present)
-        if iter.starts_line() {
-            let mut end = iter.clone();
-            end.backward_char();
-            self.apply_tag(&self.tag_table().lookup(tag_name).unwrap(), &start, &end);
-        } else {
-            self.apply_tag(&self.tag_table().lookup(tag_name).unwrap(), &start, iter);
-        }
-    }
-
-    fn apply_image_offset(
-        &self,
-        iter: &gtk::TextIter,
-        image: &str,
-        title: &str,
-        start_offset: i32,
-    ) {
-        let mut start = iter.clone();
-        start.backward_chars(iter.offset() - start_offset);
-        let tag = if title.is_empty() {
-            self.create_image_tag(image)
-        } else {
-            self.create_image_tag(format!("{} \"{}\"", image, title).as_str())
-        };
-        self.apply_tag(&tag, &start, iter);
-    }
-
-    // Convert markup for colors to the corresponding formatting and delete the markup
-    fn convert_colors(&self, tag: &str, pos_start: i32) {
-        let mut offset = pos_start;
-        while let Some((start_tag_start, start_tag_end)) =
-            self.iter_at_offset(offset).forward_search(
-                TextTagTable::md_start_tag(tag).unwrap(),
-                gtk::TextSearchFlags::VISIBLE_ONLY,
-                None,
-            )
-        {
-            let offset_sts = start_tag_start.offset();
-            let offset_ste = start_tag_end.offset();
-
-            if let Some((mut end_tag_start, mut end_tag_end)) = start_tag_end.forward_search(
-                TextTagTable::md_end_tag(tag).unwrap(),
-                gtk::TextSearchFlags::VISIBLE_ONLY,
-                None,
-            ) {
-                self.apply_tag(
-                    &self.tag_table().lookup(tag).unwrap(),
-                    &start_tag_end,
-                    &end_tag_start,
-                );
-                offset = end_tag_start.offset() - 6; // length of two tags, which get removed
-                self.delete(&mut end_tag_start, &mut end_tag_end);
-                end_tag_start.set_offset(offset_sts);
-                end_tag_end.set_offset(offset_ste);
-                self.delete(&mut end_tag_start, &mut end_tag_end);
-            }
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::texttagtable::TextTagTable;
     use gtk::prelude::TextTagExt;
 
     #[allow(dead_code)]
@@ -515,7 +145,7 @@ mod tests {
 
         for (input, output) in pairs {
             buffer.assign_markup(input);
-            assert_eq!(buffer.to_markdown().as_str(), output);
+            assert_eq!(buffer.to_markdown(&Format::default()).as_str(), output);
         }
     }
 
@@ -523,8 +153,9 @@ mod tests {
     #[test]
     fn test_simple_markdown_turnaround() {
         let buffer = buffer_new();
+        let format = Format::default();
 
-        let s1 = format!("``Code``\n\n{}\n\n**Bold Text**\n", Tag::MD_RULE);
+        let s1 = format!("``Code``\n\n{}\n\n**Bold Text**\n", markdown::MD_RULE);
 
         let strings = vec![
             "Hello world!\n",
@@ -568,11 +199,20 @@ mod tests {
             "* **Hallo Welt**\n\n* **Hallo zwei**\n\n    * **Hallo drei**\n\n    * 5 \\* 4 = 20\n\n    * **5 \\* 4 = 20**\n",
             "```\n* foo\n    * **bar**\n* _baz_\n```\n",
             "```\n* first\n* second\n\n<br/>\n\n    * inner first\n\n    * inner **second**\n* third\n```\n",
+            "| a   | b   |\n| --- | --- |\n| 1   | 2   |\n",
+            "Text\n\n| Name  | Value |\n| ----- | ----: |\n| **a** |     1 |\n\nAfter\n",
+            "* [ ] open\n\n* [x] done\n\n    * [ ] **inner**\n",
+            "Text[^1] with *two*[^note] notes\n\n[^1]: The first\n\n[^note]: The **second**\n",
+            "> Quoted *text*\n",
+            "> First\n>\n> Second\n\nAfter\n",
+            "> Level one\n>\n> > Level two\n\nNo quote\n",
+            "```rust\nfn main() {\n    let s = \"**no bold**\"; // 42\n}\n```\n",
+            "```c++ extra\nint x = 1;\n```\n\n```\nplain\n```\n",
 
         ];
 
         for s in strings {
-            assert_eq!(buffer.assign_markdown(s, true).to_markdown().as_str(), s);
+            assert_eq!(buffer.assign_markdown(s, &format, true).to_markdown(&format).as_str(), s);
         }
     }
 
@@ -580,6 +220,7 @@ mod tests {
     #[test]
     fn test_markdown_turnaround() {
         let buffer = buffer_new();
+        let format = Format::default();
 
         let pairs = vec![(
                              "* first\n* second\n    * inner first\n    * inner second\n        * second inner first\n        * second inner second\n* third\n* fourth\n",
@@ -590,19 +231,21 @@ mod tests {
                          ("**Hello _World_**_again_\n","**Hello *World****again*\n"),
                          ("**Hello _World_** _again_\n","**Hello *World*** *again*\n"),
                          ("**_text_**\n","***text***\n"),
+                         ("|a|b|\n|-|:-:|\n|1|2|\n", "| a   |  b  |\n| --- | :-: |\n| 1   |  2  |\n"),
                          ("_**text**_\n","***text***\n"),
+                         ("- [ ] open\n- [X] done\n","* [ ] open\n\n* [x] done\n"),
+                         ("[^a]: First\n\nText[^a]\n", "Text[^a]\n\n[^a]: First\n"),
                          ("*ABC**Hello***** World** *again*\n","*ABC**Hello***\\*\\* World\\*\\* *again*\n"),
-                         // ToDo: these results are broken and need to be changed
-                         // ToDo: mixed formatting in links
-                         ("[**Marko** {++Editor++} *Website*](http://www.marko-editor.com)\n", "**[Marko** {++Editor++} *Website](http://www.marko-editor.com)*\n"),
-                         // ToDo: critics markup in code blocks
-                         ("```\nHallo {++Welt++}\n```\n", "```\nHallo Welt\n```\n"),
+                         ("[**Marko** {++Editor++} *Website*](http://www.marko-editor.com)\n", "[**Marko** {++Editor++} *Website*](http://www.marko-editor.com)\n"),
 
 
         ];
 
         for (input, output) in pairs {
-            assert_eq!(buffer.assign_markdown(input, true).to_markdown().as_str(), output);
+            assert_eq!(
+                buffer.assign_markdown(input, &format, true).to_markdown(&format).as_str(),
+                output
+            );
         }
     }
 
@@ -610,11 +253,42 @@ mod tests {
     #[test]
     fn test_current_work() {
         let buffer = buffer_new();
+        let format = Format::default();
         let s = r#"
 "#;
 
         let r = s;
         // https://spec.commonmark.org/dingus/
-        assert_eq!(buffer.assign_markdown(s, true).to_markdown().as_str(), r);
+        assert_eq!(buffer.assign_markdown(s, &format, true).to_markdown(&format).as_str(), r);
+    }
+
+    #[allow(dead_code)]
+    #[test]
+    fn test_tag_mapping() {
+        let buffer = buffer_new();
+
+        // the conversion itself is tested in markdown.rs, this covers the mapping to the tags
+        let strings = vec![
+            "# Heading with **bold**\n\nText with *italic*, ``mono`` and ~~strike~~\n",
+            "{++{==***text***==}++} {--removed--} {>>comment<<}\n",
+            "[Marko Editor](http://www.marko-editor.com) and ![image](./image.png \"Title\")\n",
+            "**[Marko *Editor*](http://www.marko-editor.com)** [``mono`` and ~~strike~~](./a.md)\n",
+            "> Level one\n>\n> > Level two\n\nNo quote\n",
+            "```rust\nfn main() {\n    let s = \"**no bold**\"; // 42\n}\n```\n",
+            "```\nlet {++a++} = {--1--}2;\n```\n",
+            "Text\n\n| Name  | Value |\n| ----- | ----: |\n| **a** |     1 |\n\nAfter\n",
+            "* [ ] open\n\n* [x] done\n",
+            "Text[^1]\n\n[^1]: The note\n",
+            "Hard  \nbreak and\nsoft break\n",
+        ];
+
+        let format = Format {
+            hard_break: markdown::HardBreak::Spaces,
+            keep_soft_breaks: true,
+            ..Format::default()
+        };
+        for s in strings {
+            assert_eq!(buffer.assign_markdown(s, &format, true).to_markdown(&format).as_str(), s);
+        }
     }
 }
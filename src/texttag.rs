@@ -1,4 +1,9 @@
-use crate::textbufferext::{IMAGE_START, LINK_START};
+use crate::document::{self, Style};
+use crate::syntax::Token;
+use crate::textbufferext::{
+    CODE_LANGUAGE_START, IMAGE_START, LINK_REFERENCE_START, LINK_START, LIST_ITEM_START,
+    TABLE_START,
+};
 use gtk::prelude::TextTagExt;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -7,6 +12,7 @@ pub enum CharFormat {
     Italic,
     Mono,
     Strike,
+    Math,
 
     Red,
     Green,
@@ -26,6 +32,8 @@ pub enum ParFormat {
     H5,
     H6,
     Code,
+    Quote,
+    Math,
 }
 
 pub struct Tag {}
@@ -38,22 +46,57 @@ impl Tag {
     pub const H6: &'static str = "h6";
 
     pub const CODE: &'static str = "code";
+    pub const MATH_BLOCK: &'static str = "math_block";
+
+    // syntax highlighting in code blocks, not written to markdown
+    pub const SYNTAX_KEYWORD: &'static str = "syntax_keyword";
+    pub const SYNTAX_STRING: &'static str = "syntax_string";
+    pub const SYNTAX_COMMENT: &'static str = "syntax_comment";
+    pub const SYNTAX_NUMBER: &'static str = "syntax_number";
+    pub const SYNTAX: [&'static str; 4] =
+        [Tag::SYNTAX_KEYWORD, Tag::SYNTAX_STRING, Tag::SYNTAX_COMMENT, Tag::SYNTAX_NUMBER];
+
+    // nested quotes have their own tags
+    pub const QUOTE: &'static str = "quote";
+    pub const QUOTE_2: &'static str = "quote2";
+    pub const QUOTE_3: &'static str = "quote3";
+    pub const QUOTE_MAX_LEVEL: u32 = document::QUOTE_MAX_LEVEL;
 
     pub const BOLD: &'static str = "weight=700";
     pub const ITALIC: &'static str = "style=2";
     pub const MONO: &'static str = "family=Monospace";
     pub const STRIKE: &'static str = "strikethrough=1";
+    // formulas are not formatted and written as they are
+    pub const MATH: &'static str = "math";
 
     // inspired from critics markdown
     pub const RED: &'static str = "red"; // removal
     pub const GREEN: &'static str = "green"; // addition
     pub const BLUE: &'static str = "blue"; // comment
     pub const YELLOW: &'static str = "yellow"; // highlight
+    pub const CRITIC: [&'static str; 4] = [Tag::RED, Tag::GREEN, Tag::BLUE, Tag::YELLOW];
 
     pub const SEARCH: &'static str = "search"; // highlight for search results
 
+    pub const TABLE_HEADER: &'static str = "table_header";
+
+    pub const FOOTNOTE_REF: &'static str = "footnote_ref";
+    pub const FOOTNOTE_DEF: &'static str = "footnote_def"; // label at the start of the definition
+
+    // task list checkbox, the tag is applied to one of the checkbox chars
+    pub const TASK: &'static str = "task";
+    pub const TASK_OPEN: char = document::TASK_OPEN;
+    pub const TASK_DONE: char = document::TASK_DONE;
+
     pub const RULE: &'static str = "rule";
-    pub const MD_RULE: &'static str = "--- ---- ----- ------- ----- ---- ---";
+
+    // raw HTML is kept as it is and only shown
+    pub const HTML: &'static str = "html";
+    // YAML or TOML front matter, it can be hidden while editing
+    pub const FRONT_MATTER: &'static str = "front_matter";
+    // line breaks inside of a paragraph, only kept for the markdown
+    pub const HARD_BREAK: &'static str = "hard_break";
+    pub const SOFT_BREAK: &'static str = "soft_break";
 
     pub fn from_char_format(format: &CharFormat) -> &'static str {
         match format {
@@ -61,6 +104,7 @@ impl Tag {
             CharFormat::Italic => Tag::ITALIC,
             CharFormat::Mono => Tag::MONO,
             CharFormat::Strike => Tag::STRIKE,
+            CharFormat::Math => Tag::MATH,
             CharFormat::Red => Tag::RED,
             CharFormat::Green => Tag::GREEN,
             CharFormat::Blue => Tag::BLUE,
@@ -77,6 +121,68 @@ impl Tag {
             ParFormat::H5 => Tag::H5,
             ParFormat::H6 => Tag::H6,
             ParFormat::Code => Tag::CODE,
+            ParFormat::Quote => Tag::QUOTE,
+            ParFormat::Math => Tag::MATH_BLOCK,
+        }
+    }
+
+    pub fn from_token(token: &Token) -> &'static str {
+        match token {
+            Token::Keyword => Tag::SYNTAX_KEYWORD,
+            Token::String => Tag::SYNTAX_STRING,
+            Token::Comment => Tag::SYNTAX_COMMENT,
+            Token::Number => Tag::SYNTAX_NUMBER,
+        }
+    }
+
+    // Links, images, tables, code languages and list items have tags with dynamic names.
+    pub fn from_style(style: &Style) -> Option<&'static str> {
+        match style {
+            Style::Heading(1) => Some(Tag::H1),
+            Style::Heading(2) => Some(Tag::H2),
+            Style::Heading(3) => Some(Tag::H3),
+            Style::Heading(4) => Some(Tag::H4),
+            Style::Heading(5) => Some(Tag::H5),
+            Style::Heading(6) => Some(Tag::H6),
+            Style::Quote(level) => Some(Tag::quote_tag(*level)),
+            Style::Code => Some(Tag::CODE),
+            Style::MathBlock => Some(Tag::MATH_BLOCK),
+            Style::TableHeader => Some(Tag::TABLE_HEADER),
+            Style::Rule => Some(Tag::RULE),
+            Style::Bold => Some(Tag::BOLD),
+            Style::Italic => Some(Tag::ITALIC),
+            Style::Mono => Some(Tag::MONO),
+            Style::Math => Some(Tag::MATH),
+            Style::Strike => Some(Tag::STRIKE),
+            Style::FootnoteRef => Some(Tag::FOOTNOTE_REF),
+            Style::FootnoteDef => Some(Tag::FOOTNOTE_DEF),
+            Style::Task => Some(Tag::TASK),
+            Style::Addition => Some(Tag::GREEN),
+            Style::Removal => Some(Tag::RED),
+            Style::Comment => Some(Tag::BLUE),
+            Style::Highlight => Some(Tag::YELLOW),
+            Style::Html => Some(Tag::HTML),
+            Style::FrontMatter => Some(Tag::FRONT_MATTER),
+            Style::HardBreak => Some(Tag::HARD_BREAK),
+            Style::SoftBreak => Some(Tag::SOFT_BREAK),
+            _ => None,
+        }
+    }
+
+    pub fn quote_tag(level: u32) -> &'static str {
+        match level {
+            0 | 1 => Tag::QUOTE,
+            2 => Tag::QUOTE_2,
+            _ => Tag::QUOTE_3,
+        }
+    }
+
+    pub fn quote_level(tag: &str) -> Option<u32> {
+        match tag {
+            Tag::QUOTE => Some(1),
+            Tag::QUOTE_2 => Some(2),
+            Tag::QUOTE_3 => Some(3),
+            _ => None,
         }
     }
 
@@ -98,8 +204,15 @@ pub trait TextTagExt2 {
 
     fn get_image(&self) -> Option<String>;
     fn get_link(&self) -> Option<String>;
+    // The label and the target of a reference link
+    fn get_link_reference(&self) -> Option<(String, String)>;
+    fn get_table(&self) -> Option<String>;
+    fn get_code_language(&self) -> Option<String>;
+    fn get_list_level(&self) -> Option<u32>;
 
     fn get_par_format(&self) -> Option<ParFormat>;
+    // None for tags, which are not written to markdown, e.g. syntax highlighting
+    fn get_style(&self) -> Option<Style>;
 }
 
 impl TextTagExt2 for gtk::TextTag {
@@ -127,6 +240,55 @@ impl TextTagExt2 for gtk::TextTag {
         }
     }
 
+    fn get_link_reference(&self) -> Option<(String, String)> {
+        let name = self.get_name();
+        if !name.starts_with(LINK_REFERENCE_START) {
+            return None;
+        }
+        // the label is in brackets, which are escaped inside the label
+        let reference = &name[LINK_REFERENCE_START.len() + 1..];
+        let mut escaped = false;
+        for (i, c) in reference.char_indices() {
+            match c {
+                ']' if !escaped => {
+                    return Some((reference[..i].to_string(), reference[i + 1..].to_string()))
+                }
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        None
+    }
+
+    fn get_table(&self) -> Option<String> {
+        let mut name = self.get_name();
+        if name.starts_with(TABLE_START) {
+            name.replace_range(..TABLE_START.len(), "");
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    fn get_code_language(&self) -> Option<String> {
+        let mut name = self.get_name();
+        if name.starts_with(CODE_LANGUAGE_START) {
+            name.replace_range(..CODE_LANGUAGE_START.len(), "");
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    fn get_list_level(&self) -> Option<u32> {
+        let name = self.get_name();
+        if name.starts_with(LIST_ITEM_START) {
+            name[LIST_ITEM_START.len()..].parse().ok()
+        } else {
+            None
+        }
+    }
+
     fn get_par_format(&self) -> Option<ParFormat> {
         match self.get_name().as_str() {
             Tag::H1 => Some(ParFormat::H1),
@@ -136,7 +298,54 @@ impl TextTagExt2 for gtk::TextTag {
             Tag::H5 => Some(ParFormat::H5),
             Tag::H6 => Some(ParFormat::H6),
             Tag::CODE => Some(ParFormat::Code),
+            Tag::MATH_BLOCK => Some(ParFormat::Math),
+            Tag::QUOTE | Tag::QUOTE_2 | Tag::QUOTE_3 => Some(ParFormat::Quote),
             _ => None,
         }
     }
+
+    fn get_style(&self) -> Option<Style> {
+        if let Some(image) = self.get_image() {
+            return Some(Style::Image(image));
+        } else if let Some(link) = self.get_link() {
+            return Some(Style::Link(link));
+        } else if let Some((label, link)) = self.get_link_reference() {
+            return Some(Style::LinkReference(label, link));
+        } else if let Some(alignments) = self.get_table() {
+            return Some(Style::Table(alignments));
+        } else if let Some(language) = self.get_code_language() {
+            return Some(Style::CodeLanguage(language));
+        } else if let Some(level) = self.get_list_level() {
+            return Some(Style::ListItem(level));
+        }
+        match self.get_name().as_str() {
+            Tag::H1 => Some(Style::Heading(1)),
+            Tag::H2 => Some(Style::Heading(2)),
+            Tag::H3 => Some(Style::Heading(3)),
+            Tag::H4 => Some(Style::Heading(4)),
+            Tag::H5 => Some(Style::Heading(5)),
+            Tag::H6 => Some(Style::Heading(6)),
+            Tag::CODE => Some(Style::Code),
+            Tag::MATH_BLOCK => Some(Style::MathBlock),
+            Tag::TABLE_HEADER => Some(Style::TableHeader),
+            Tag::RULE => Some(Style::Rule),
+            Tag::BOLD => Some(Style::Bold),
+            Tag::ITALIC => Some(Style::Italic),
+            Tag::MONO => Some(Style::Mono),
+            Tag::MATH => Some(Style::Math),
+            Tag::STRIKE => Some(Style::Strike),
+            Tag::FOOTNOTE_REF => Some(Style::FootnoteRef),
+            Tag::FOOTNOTE_DEF => Some(Style::FootnoteDef),
+            Tag::TASK => Some(Style::Task),
+            Tag::GREEN => Some(Style::Addition),
+            Tag::RED => Some(Style::Removal),
+            Tag::BLUE => Some(Style::Comment),
+            Tag::YELLOW => Some(Style::Highlight),
+            Tag::HTML => Some(Style::Html),
+            Tag::FRONT_MATTER => Some(Style::FrontMatter),
+            Tag::HARD_BREAK => Some(Style::HardBreak),
+            Tag::SOFT_BREAK => Some(Style::SoftBreak),
+            name => Tag::quote_level(name).map(Style::Quote),
+        }
+    }
 }
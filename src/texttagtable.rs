@@ -15,30 +15,6 @@ pub struct TextTagTable {
 }
 
 impl TextTagTable {
-    const MD_H1: &'static str = "# ";
-    const MD_H2: &'static str = "## ";
-    const MD_H3: &'static str = "### ";
-    const MD_H4: &'static str = "#### ";
-    const MD_H5: &'static str = "##### ";
-    const MD_H6: &'static str = "###### ";
-
-    const MD_CODE_START: &'static str = "```\n";
-    const MD_CODE_END: &'static str = "\n```";
-
-    const MD_BOLD: &'static str = "**";
-    const MD_ITALIC: &'static str = "*";
-    const MD_MONO: &'static str = "``";
-    const MD_STRIKE: &'static str = "~~";
-
-    const MD_RED: &'static str = "{--";
-    const MD_RED_END: &'static str = "--}";
-    const MD_GREEN: &'static str = "{++";
-    const MD_GREEN_END: &'static str = "++}";
-    const MD_BLUE: &'static str = "{>>";
-    const MD_BLUE_END: &'static str = "<<}";
-    const MD_YELLOW: &'static str = "{==";
-    const MD_YELLOW_END: &'static str = "==}";
-
     pub fn new() -> Self {
         let table = gtk::TextTagTable::new();
 
@@ -78,6 +54,17 @@ impl TextTagTable {
         tag_h6.set_pixels_above_lines(4);
         tag_h6.set_pixels_below_lines(4);
 
+        let quote = gdk::RGBA { red: 0.3f32, green: 0.5f32, blue: 0.3f32, alpha: 0.08f32 };
+        let quote_text = gdk::RGBA { red: 0.35f32, green: 0.35f32, blue: 0.35f32, alpha: 1f32 };
+        for level in 1..=Tag::QUOTE_MAX_LEVEL {
+            let tag_quote = TextTagTable::create_tag(Tag::quote_tag(level), &table);
+            tag_quote.set_paragraph_background_rgba(Some(&quote));
+            tag_quote.set_foreground_rgba(Some(&quote_text));
+            tag_quote.set_left_margin(20 * level as i32);
+            tag_quote.set_pixels_above_lines(2);
+            tag_quote.set_pixels_below_lines(2);
+        }
+
         let tag_bold = TextTagTable::create_tag(Tag::BOLD, &table);
         tag_bold.set_weight(gtk::pango::ffi::PANGO_WEIGHT_BOLD);
 
@@ -96,6 +83,54 @@ impl TextTagTable {
         tag_code.set_right_margin(30);
         tag_code.set_indent(2);
 
+        let math = gdk::RGBA { red: 0.5f32, green: 0.2f32, blue: 0f32, alpha: 1f32 };
+        let tag_math = TextTagTable::create_tag(Tag::MATH, &table);
+        tag_math.set_family(Some("Monospace"));
+        tag_math.set_foreground_rgba(Some(&math));
+
+        let tag_math_block = TextTagTable::create_tag(Tag::MATH_BLOCK, &table);
+        tag_math_block.set_family(Some("Monospace"));
+        tag_math_block.set_foreground_rgba(Some(&math));
+        tag_math_block.set_paragraph_background_rgba(Some(&grey));
+        tag_math_block.set_left_margin(30);
+        tag_math_block.set_right_margin(30);
+        tag_math_block.set_justification(gtk::Justification::Center);
+
+        let tag_keyword = TextTagTable::create_tag(Tag::SYNTAX_KEYWORD, &table);
+        let keyword = gdk::RGBA { red: 0.6f32, green: 0.1f32, blue: 0.6f32, alpha: 1f32 };
+        tag_keyword.set_foreground_rgba(Some(&keyword));
+
+        let tag_string = TextTagTable::create_tag(Tag::SYNTAX_STRING, &table);
+        let string = gdk::RGBA { red: 0.1f32, green: 0.5f32, blue: 0.1f32, alpha: 1f32 };
+        tag_string.set_foreground_rgba(Some(&string));
+
+        let tag_comment = TextTagTable::create_tag(Tag::SYNTAX_COMMENT, &table);
+        let comment = gdk::RGBA { red: 0.5f32, green: 0.5f32, blue: 0.5f32, alpha: 1f32 };
+        tag_comment.set_foreground_rgba(Some(&comment));
+        tag_comment.set_style(gtk::pango::Style::Italic);
+
+        let tag_number = TextTagTable::create_tag(Tag::SYNTAX_NUMBER, &table);
+        let number = gdk::RGBA { red: 0.1f32, green: 0.3f32, blue: 0.8f32, alpha: 1f32 };
+        tag_number.set_foreground_rgba(Some(&number));
+
+        let tag_table_header = TextTagTable::create_tag(Tag::TABLE_HEADER, &table);
+        tag_table_header.set_weight(gtk::pango::ffi::PANGO_WEIGHT_BOLD);
+
+        let footnote = gdk::RGBA { red: 0f32, green: 0.3f32, blue: 0.8f32, alpha: 1f32 };
+        let tag_footnote_ref = TextTagTable::create_tag(Tag::FOOTNOTE_REF, &table);
+        tag_footnote_ref.set_foreground_rgba(Some(&footnote));
+        tag_footnote_ref.set_rise(5 * gtk::pango::SCALE);
+        tag_footnote_ref.set_scale(0.8f64);
+
+        let tag_footnote_def = TextTagTable::create_tag(Tag::FOOTNOTE_DEF, &table);
+        tag_footnote_def.set_foreground_rgba(Some(&footnote));
+        tag_footnote_def.set_weight(gtk::pango::ffi::PANGO_WEIGHT_BOLD);
+
+        let tag_task = TextTagTable::create_tag(Tag::TASK, &table);
+        let task = gdk::RGBA { red: 0.2f32, green: 0.4f32, blue: 0.8f32, alpha: 1f32 };
+        tag_task.set_foreground_rgba(Some(&task));
+        tag_task.set_weight(gtk::pango::ffi::PANGO_WEIGHT_BOLD);
+
         let tag_strike = TextTagTable::create_tag(Tag::STRIKE, &table);
         tag_strike.set_strikethrough(true);
 
@@ -121,6 +156,22 @@ impl TextTagTable {
 
         let _tag_rule = TextTagTable::create_tag(Tag::RULE, &table);
 
+        let tag_html = TextTagTable::create_tag(Tag::HTML, &table);
+        let html = gdk::RGBA { red: 0.45f32, green: 0.45f32, blue: 0.45f32, alpha: 1f32 };
+        tag_html.set_family(Some("Monospace"));
+        tag_html.set_foreground_rgba(Some(&html));
+        tag_html.set_background_rgba(Some(&grey));
+        tag_html.set_scale(0.9f64);
+
+        let tag_front_matter = TextTagTable::create_tag(Tag::FRONT_MATTER, &table);
+        tag_front_matter.set_family(Some("Monospace"));
+        tag_front_matter.set_foreground_rgba(Some(&html));
+        tag_front_matter.set_paragraph_background_rgba(Some(&grey));
+        tag_front_matter.set_scale(0.9f64);
+
+        let _tag_hard_break = TextTagTable::create_tag(Tag::HARD_BREAK, &table);
+        let _tag_soft_break = TextTagTable::create_tag(Tag::SOFT_BREAK, &table);
+
         Self { table }
     }
 
@@ -137,46 +188,4 @@ impl TextTagTable {
     pub fn get_tag(&self, name: &str) -> Option<gtk::TextTag> {
         self.table.lookup(name)
     }
-
-    pub fn md_start_tag(tag: &str) -> Option<&'static str> {
-        match tag {
-            Tag::H1 => Some(TextTagTable::MD_H1),
-            Tag::H2 => Some(TextTagTable::MD_H2),
-            Tag::H3 => Some(TextTagTable::MD_H3),
-            Tag::H4 => Some(TextTagTable::MD_H4),
-            Tag::H5 => Some(TextTagTable::MD_H5),
-            Tag::H6 => Some(TextTagTable::MD_H6),
-            Tag::CODE => Some(TextTagTable::MD_CODE_START),
-            Tag::BOLD => Some(TextTagTable::MD_BOLD),
-            Tag::ITALIC => Some(TextTagTable::MD_ITALIC),
-            Tag::MONO => Some(TextTagTable::MD_MONO),
-            Tag::STRIKE => Some(TextTagTable::MD_STRIKE),
-            Tag::RED => Some(TextTagTable::MD_RED),
-            Tag::GREEN => Some(TextTagTable::MD_GREEN),
-            Tag::BLUE => Some(TextTagTable::MD_BLUE),
-            Tag::YELLOW => Some(TextTagTable::MD_YELLOW),
-            _ => None,
-        }
-    }
-
-    pub(crate) fn md_end_tag(tag: &str) -> Option<&'static str> {
-        match tag {
-            Tag::H1 => Some(""),
-            Tag::H2 => Some(""),
-            Tag::H3 => Some(""),
-            Tag::H4 => Some(""),
-            Tag::H5 => Some(""),
-            Tag::H6 => Some(""),
-            Tag::CODE => Some(TextTagTable::MD_CODE_END),
-            Tag::BOLD => Some(TextTagTable::MD_BOLD),
-            Tag::ITALIC => Some(TextTagTable::MD_ITALIC),
-            Tag::MONO => Some(TextTagTable::MD_MONO),
-            Tag::STRIKE => Some(TextTagTable::MD_STRIKE),
-            Tag::RED => Some(TextTagTable::MD_RED_END),
-            Tag::GREEN => Some(TextTagTable::MD_GREEN_END),
-            Tag::BLUE => Some(TextTagTable::MD_BLUE_END),
-            Tag::YELLOW => Some(TextTagTable::MD_YELLOW_END),
-            _ => None,
-        }
-    }
 }
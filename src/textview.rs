@@ -1,5 +1,12 @@
+use crate::document::NEWLINE;
+use crate::html;
+use crate::inputrules::{input_rule, is_trigger, InputRule};
+use crate::list;
+use crate::markdown::{self, Format, MD_RULE};
+use crate::print;
+use crate::source::Source;
 use crate::textbufferext::{get_file_name, is_file, TextBufferExt2};
-use crate::textbuffermd::{TextBufferMd, NEWLINE};
+use crate::textbuffermd::TextBufferMd;
 use crate::texttag::{CharFormat, ParFormat, Tag, TextTagExt2, COLORS};
 use crate::texttagmanager::{TextEdit, TextTagManager};
 use crate::textviewext::TextViewExt2;
@@ -20,6 +27,7 @@ use regex::Regex;
 use crate::gdk_glue::{ColorCreator, GetColor};
 use std::cell::RefCell;
 use std::ops::Deref;
+use std::path::Path;
 use std::rc::Rc;
 use std::thread;
 
@@ -29,10 +37,12 @@ mod keys {
 
 const MARGIN: i32 = 10;
 const TAB_WIDTH: i32 = 4;
+const REVIEW_EXCERPT: usize = 60; // chars of a critic span shown in the review panel
 
 pub struct LinkData {
     text: String,
     link: String,
+    reference: String, // label of a reference link, empty for an inline link
     is_image: bool,
 }
 
@@ -86,6 +96,7 @@ struct LinkEdit {
     link_edit_bar: gtk::SearchBar,
     edt_link_name: gtk::Entry,
     edt_link_target: gtk::Entry,
+    edt_link_reference: gtk::Entry,
     btn_accept_link: gtk::Button,
     btn_cancel_link: gtk::Button,
     btn_fetch_title: gtk::Button,
@@ -99,6 +110,7 @@ impl LinkEdit {
             link_edit_bar: builder_get!(b("link_edit_bar")),
             edt_link_name: builder_get!(b("edt_link_name")),
             edt_link_target: builder_get!(b("edt_link_target")),
+            edt_link_reference: builder_get!(b("edt_link_reference")),
             btn_accept_link: builder_get!(b("btn_accept_link")),
             btn_cancel_link: builder_get!(b("btn_cancel_link")),
             btn_fetch_title: builder_get!(b("btn_fetch_title")),
@@ -109,6 +121,8 @@ impl LinkEdit {
         this.btn_cancel_link.connect_clicked(connect!(this.reject()));
         this.btn_fetch_title.connect_clicked(connect!(this.fetch_title()));
         this.edt_link_name.connect_activate(connect!(this.accept()));
+        this.edt_link_reference.connect_activate(connect!(this.accept()));
+        this.btn_is_image.connect_toggled(connect!(this.update_reference()));
         this
     }
 
@@ -120,7 +134,9 @@ impl LinkEdit {
         self.edt_link_name.set_text(&link_data.text);
         self.edt_link_target.set_text(&link_data.link);
         self.link_edit_bar.set_search_mode(true);
+        self.edt_link_reference.set_text(&link_data.reference);
         self.btn_is_image.set_active(link_data.is_image);
+        self.update_reference();
 
         if link_data.link.is_empty() || link_data.link == link_data.text {
             lazy_static! {
@@ -149,11 +165,17 @@ impl LinkEdit {
                 .collect::<Vec<&str>>()
                 .join(" "),
             link: String::from(self.edt_link_target.text().as_str().trim()),
+            reference: String::from(self.edt_link_reference.text().as_str().trim()),
             is_image: self.btn_is_image.is_active(),
         };
         (self.accept_link_cb.borrow())(Some(&link_data));
     }
 
+    // images are always written inline
+    fn update_reference(&self) {
+        self.edt_link_reference.set_sensitive(!self.btn_is_image.is_active());
+    }
+
     pub fn reject(&self) {
         self.hide();
         (self.accept_link_cb.borrow())(None);
@@ -360,12 +382,18 @@ pub struct TextView {
     textview: gtk::TextView,
     link_edit: Rc<LinkEdit>,
     search_bar: Rc<SearchBar>,
+    code_language_bar: gtk::SearchBar,
+    edt_code_language: gtk::Entry,
     activate_link_cb: OpenLinkCb,
     top_level: gtk::Widget,
     is_editable: Rc<RefCell<bool>>,
     link_start: gtk::TextMark,
     link_end: gtk::TextMark,
     colors: Rc<RefCell<Colors>>,
+    source: Rc<RefCell<Option<Source>>>, // the loaded markdown for saving with minimal changes
+    format: Rc<RefCell<Format>>,         // line breaks and empty lines in the markdown
+    suggesting: Rc<RefCell<bool>>,       // edits are tracked as critic additions and removals
+    input_rules: Rc<RefCell<bool>>,      // typed markdown is converted to its format
 }
 
 impl TextView {
@@ -394,6 +422,10 @@ impl TextView {
             move || -> gtk::TextView { t.clone() }
         }));
 
+        let code_language_bar: gtk::SearchBar = builder_get!(b("code_language_bar"));
+        let edt_code_language: gtk::Entry = builder_get!(b("edt_code_language"));
+        let btn_accept_code_language: gtk::Button = builder_get!(b("btn_accept_code_language"));
+
         let b: gtk::Box = builder_get!(b("container"));
         let top_level = b.upcast::<gtk::Widget>();
 
@@ -408,12 +440,18 @@ impl TextView {
             textview,
             link_edit,
             search_bar,
+            code_language_bar,
+            edt_code_language,
             top_level,
             activate_link_cb,
             is_editable: Rc::new(RefCell::from(true)),
             link_start,
             link_end,
             colors: Rc::new(RefCell::new(Colors::new())),
+            source: Rc::new(RefCell::new(None)),
+            format: Rc::new(RefCell::new(Format::default())),
+            suggesting: Rc::new(RefCell::from(false)),
+            input_rules: Rc::new(RefCell::from(true)),
         };
         this.top_level.add_controller(&this.get_key_press_handler_background());
         this.textview.add_controller(&this.get_key_press_handler());
@@ -431,10 +469,15 @@ impl TextView {
         });
 
         this.link_edit.set_accept_link_cb(connect_fwd1!(this.accept_link()));
+        this.edt_code_language.connect_activate(connect!(this.accept_code_language()));
+        btn_accept_code_language.connect_clicked(connect!(this.accept_code_language()));
+        this.code_language_bar
+            .connect_search_mode_enabled_notify(connect!(this.on_code_language_bar()));
 
         this.buffer
             .connect_local("insert-text", true, connect_fwd1!(this.buffer_do_insert_text()))
             .unwrap();
+        this.buffer.connect_changed(connect!(this.buffer_changed()));
 
         this.update_colors(false);
 
@@ -487,18 +530,154 @@ impl TextView {
         *self.is_editable.borrow().deref()
     }
 
+    pub fn set_suggesting(&self, suggesting: bool) {
+        *self.suggesting.borrow_mut() = suggesting;
+    }
+
+    fn is_suggesting(&self) -> bool {
+        *self.suggesting.borrow().deref()
+    }
+
     fn buffer_do_insert_text(&self, values: &[Value]) -> Option<Value> {
         let buffer = &values[0].get::<gtk::TextBuffer>().unwrap();
         let iter = &values[1].get::<gtk::TextIter>().unwrap();
-        let _text = values[2].get::<&str>().unwrap();
+        let text = values[2].get::<&str>().unwrap();
         let count = values[3].get::<i32>().unwrap();
 
         let mut start = iter.clone();
         start.backward_chars(count);
         self.tags.for_each_edit_tag(|tag: &gtk::TextTag| buffer.apply_tag(tag, iter, &start));
+        if self.is_suggesting() {
+            buffer.remove_tag_by_name(Tag::RED, &start, iter);
+            buffer.apply_tag_by_name(Tag::GREEN, &start, iter);
+        }
+        if *self.input_rules.borrow() {
+            self.continue_code_block(&start, iter);
+            match text.chars().next() {
+                Some(typed) if count == 1 && is_trigger(typed) && !self.is_suggesting() => {
+                    // the buffer can't be changed while inserting, so the rule is applied
+                    // afterwards and can be undone on its own
+                    let this = self.clone();
+                    let offset = iter.offset();
+                    glib::idle_add_local(move || {
+                        this.apply_input_rule(offset, typed);
+                        glib::Continue(false)
+                    });
+                }
+                _ => {}
+            }
+        }
         None
     }
 
+    // Programmatic changes of the buffer aren't typed markdown, e.g. loading or undoing.
+    fn without_input_rules<F: FnOnce()>(&self, f: F) {
+        let input_rules = self.input_rules.replace(false);
+        f();
+        self.input_rules.replace(input_rules);
+    }
+
+    // Text typed in front of a char of a code block belongs to the block, e.g. to a new empty one.
+    fn continue_code_block(&self, start: &gtk::TextIter, end: &gtk::TextIter) {
+        let code = self.buffer.tag_table().lookup(Tag::CODE).unwrap();
+        if !end.has_tag(&code) {
+            return;
+        }
+        for tag in end.tags() {
+            if tag == code || tag.get_code_language().is_some() {
+                self.buffer.apply_tag(&tag, start, end);
+            }
+        }
+    }
+
+    // Markdown typed into code and formulas stays text.
+    fn is_literal(&self, iter: &gtk::TextIter) -> bool {
+        let mut before = iter.clone();
+        before.backward_char();
+        [Tag::CODE, Tag::MONO, Tag::MATH, Tag::MATH_BLOCK].iter().any(|name| {
+            let tag = self.buffer.tag_table().lookup(name).unwrap();
+            iter.has_tag(&tag) || before.has_tag(&tag)
+        })
+    }
+
+    // Converts the markdown completed by the char typed in front of offset.
+    fn apply_input_rule(&self, offset: i32, typed: char) {
+        let b = &self.buffer;
+        let mut typed_iter = b.iter_at_offset(offset);
+        // the text might have changed in the meantime
+        if !typed_iter.backward_char() || typed_iter.char() != typed {
+            return;
+        }
+        if self.is_literal(&typed_iter) {
+            return;
+        }
+        // a typed newline ends the previous line
+        let line = typed_iter.line();
+        let line_start = b.iter_at_line(line).unwrap();
+        let text = b.text(&line_start, &typed_iter, false);
+        let rule = match input_rule(text.as_str(), typed) {
+            Some(rule) => rule,
+            None => return,
+        };
+        let line_offset = line_start.offset();
+        let iter_at = |chars: usize| b.iter_at_offset(line_offset + chars as i32);
+        let len = text.chars().count();
+
+        b.begin_user_action();
+        match rule {
+            InputRule::Paragraph(prefix, style) => {
+                b.delete(&mut iter_at(0), &mut iter_at(prefix));
+                let start = iter_at(0);
+                let mut end = start.clone();
+                if !end.ends_line() {
+                    end.forward_to_line_end();
+                }
+                if start == end {
+                    // an empty line gets the format with the text typed next
+                    self.tags.toggle_tag(Tag::from_style(&style).unwrap());
+                } else {
+                    for tag in start.tags().iter().filter(|tag| tag.get_par_format().is_some()) {
+                        b.remove_tag(tag, &start, &end);
+                    }
+                    b.apply_tag(&b.get_style_tag(&style), &start, &end);
+                }
+            }
+            InputRule::ListItem(indent) => {
+                b.set_list_level(line, Some(list::level(indent)));
+                b.renumber_lists();
+            }
+            InputRule::CodeBlock(language) => {
+                // the newline of the empty line is the content of the block
+                b.delete(&mut iter_at(0), &mut iter_at(len));
+                b.apply_tag_by_name(Tag::CODE, &iter_at(0), &iter_at(1));
+                if !language.is_empty() {
+                    b.apply_tag(&b.create_code_language_tag(&language), &iter_at(0), &iter_at(1));
+                }
+                b.place_cursor(&iter_at(0));
+            }
+            InputRule::Rule => {
+                b.delete(&mut iter_at(0), &mut iter_at(len));
+                b.insert(&mut iter_at(0), MD_RULE);
+                b.apply_tag_by_name(Tag::RULE, &iter_at(0), &iter_at(MD_RULE.len()));
+            }
+            InputRule::Inline(start, marker, style) => {
+                let end = len + 1;
+                b.delete(&mut iter_at(end - marker), &mut iter_at(end));
+                b.delete(&mut iter_at(start), &mut iter_at(start + marker));
+                b.apply_tag(&b.get_style_tag(&style), &iter_at(start), &iter_at(end - 2 * marker));
+            }
+        }
+        b.end_user_action();
+    }
+
+    // Keeps the syntax highlighting of the code block at the cursor up to date
+    fn buffer_changed(&self) {
+        let cursor = self.buffer.get_insert_iter();
+        if let Some((start, end)) = self.buffer.get_code_block_bounds(&cursor) {
+            self.buffer.highlight_code(&start, &end);
+        }
+    }
+
     fn get_key_press_handler(&self) -> EventControllerKey {
         let controller = EventControllerKey::new();
         controller.connect_key_pressed({
@@ -507,6 +686,14 @@ impl TextView {
                   key: gdk::keys::Key,
                   _code: u32,
                   modifier: gdk::ModifierType| {
+                // typing over a selection keeps the selected text as removal
+                if this.is_suggesting()
+                    && this.buffer.has_selection()
+                    && (modifier & gdk::ModifierType::CONTROL_MASK).is_empty()
+                    && matches!(key.to_unicode(), Some(c) if !c.is_control())
+                {
+                    this.suggest_deletion(true);
+                }
                 if modifier == gdk::ModifierType::CONTROL_MASK {
                     match key {
                         keys::_0 => this.par_format(None),
@@ -519,10 +706,21 @@ impl TextView {
                         keys::b => this.char_format(CharFormat::Bold),
                         keys::d => this.char_format(CharFormat::Strike),
                         keys::i => this.char_format(CharFormat::Italic),
+                        keys::k => this.char_format(CharFormat::Math),
                         keys::f => this.open_search(),
+                        keys::g => this.edit_code_language(),
                         keys::l => this.edit_link(),
                         keys::n => this.apply_text_clear(),
+                        keys::r => this.par_format(Some(ParFormat::Quote)),
                         keys::t => this.char_format(CharFormat::Mono),
+                        keys::x if this.is_suggesting() && this.buffer.has_selection() => {
+                            this.buffer.copy_clipboard(&this.textview.clipboard());
+                            this.suggest_deletion(true);
+                        }
+                        keys::v if this.is_suggesting() && this.buffer.has_selection() => {
+                            this.suggest_deletion(true);
+                            return Inhibit(false);
+                        }
                         keys::y => this.redo(),
                         keys::z => {
                             if (modifier & gdk::ModifierType::SHIFT_MASK).is_empty() {
@@ -533,6 +731,7 @@ impl TextView {
                         }
                         keys::Down => this.text_move(false),
                         keys::Up => this.text_move(true),
+                        keys::KP_Enter | keys::Return => this.toggle_task(),
                         _ => {
                             println!("Unmapped key {} mod {} code {}.", key, modifier, _code);
                             return Inhibit(false);
@@ -542,6 +741,8 @@ impl TextView {
                 } else if modifier == gdk::ModifierType::SHIFT_MASK {
                     match key {
                         keys::Tab | keys::ISO_Left_Tab => this.remove_tab(),
+                        keys::KP_Enter | keys::Return => this.insert_hard_break(),
+                        keys::F9 => this.resolve_change(false),
                         _ => return Inhibit(false),
                     }
                     return Inhibit(true);
@@ -552,12 +753,24 @@ impl TextView {
                         keys::F2 => this.char_format(CharFormat::Red),
                         keys::F3 => this.char_format(CharFormat::Yellow),
                         keys::F4 => this.char_format(CharFormat::Blue),
+                        keys::F5 => this.goto_change(true),
+                        keys::F6 => this.goto_change(false),
                         keys::F7 => this.dump(),
                         keys::F8 => this.turnaround(),
+                        keys::F9 => this.resolve_change(true),
                         keys::Tab | keys::ISO_Left_Tab => this.insert_tab(),
+                        keys::BackSpace if this.is_suggesting() => this.suggest_deletion(false),
+                        keys::Delete | keys::KP_Delete if this.is_suggesting() => {
+                            this.suggest_deletion(true)
+                        }
                         keys::KP_Enter | keys::Return => {
+                            if this.is_suggesting() && this.buffer.has_selection() {
+                                this.suggest_deletion(true);
+                            }
                             this.tags.text_edit(TextEdit::NewLine);
-                            return Inhibit(false);
+                            if !this.continue_list() {
+                                return Inhibit(false);
+                            }
                         }
                         _ => return Inhibit(false),
                     }
@@ -598,21 +811,48 @@ impl TextView {
         gesture.connect_pressed({
             let this = self.clone();
             move |gesture, n_press, x, y| {
-                if this.buffer.has_selection()
-                    || n_press < 2
-                    || gesture.clone().upcast::<gtk::GestureSingle>().button() > 1
-                {
-                    return;
+                let button = gesture.clone().upcast::<gtk::GestureSingle>().button();
+                if n_press == 1 && button == 1 && this.is_editable() {
+                    if let Some(iter) = this.textview.get_iter_at_coord(x, y) {
+                        if this.buffer.toggle_task(&iter) {
+                            return;
+                        }
+                    }
                 }
 
-                if let Some(link) = this.textview.get_link_at_location(x, y) {
-                    (this.activate_link_cb.as_ref().borrow().deref())(link.as_str());
+                if this.buffer.has_selection() || n_press < 2 || button > 1 {
+                    return;
                 }
+
+                this.activate_link_at(x, y);
             }
         });
         gesture
     }
 
+    // Footnotes are links within the document, everything else is handed to the callback
+    fn activate_link_at(&self, x: f64, y: f64) {
+        if let Some(iter) = self.textview.get_iter_at_coord(x, y) {
+            if let Some((label, tag)) = self.buffer.get_footnote_at_iter(&iter) {
+                let target = if tag.get_name() == Tag::FOOTNOTE_REF {
+                    Tag::FOOTNOTE_DEF
+                } else {
+                    Tag::FOOTNOTE_REF
+                };
+                let target = self.buffer.tag_table().lookup(target).unwrap();
+                if let Some((mut start, end)) = self.buffer.find_tag_with_text(&target, &label) {
+                    self.buffer.select_range(&start, &end);
+                    self.textview.scroll_to_iter(&mut start, 0.05, false, 0., 0.);
+                }
+                return;
+            }
+        }
+
+        if let Some(link) = self.textview.get_link_at_location(x, y) {
+            (self.activate_link_cb.as_ref().borrow().deref())(link.as_str());
+        }
+    }
+
     fn get_drag_handler(&self) -> gtk::DragSource {
         let drag = gtk::DragSource::new();
         drag.connect_prepare({
@@ -699,16 +939,22 @@ impl TextView {
         // ToDo: this might be a problem for empty lines
         end.forward_to_line_end();
 
-        self.buffer.apply_paragraph_format(format, &start, &end);
+        if let Some(ParFormat::Quote) = format {
+            // repeating the quote format increases the nesting level
+            let level = self.buffer.get_quote_level_at_iter(&start) % Tag::QUOTE_MAX_LEVEL + 1;
+            self.buffer.apply_quote_level(level, &start, &end);
+        } else {
+            self.buffer.apply_paragraph_format(format, &start, &end);
+        }
     }
 
-    // Changing the format of the current selection/the current word/the cursor
-    // Decide if the format is applied or cleared:
-    // * for a selection the first character shall decide
-    // * for a word the format at the cursor shall decide
+    // Changing the format of the current selection/the link or word at the cursor/the cursor
+    // * a selection is formatted line by line without the newlines and code or math blocks
+    // * links and images are always formatted completely
     // * for the cursor, the tag manager knows the current format
-    // The MONO format is used as long as the selection doesn't consist of complete lines
-    // ToDo: The implementation is far from complete
+    // The format is applied if any part lacks it, otherwise it is removed. Colors replace each
+    // other, as critic marks can't be nested.
+    // The MONO and MATH formats of a selection of complete lines create a code or math block.
     pub fn char_format(&self, format: CharFormat) {
         if !self.is_editable() {
             return;
@@ -717,54 +963,92 @@ impl TextView {
         let tag_str = Tag::from_char_format(&format);
         let b = &self.buffer;
 
-        let toggle_tag = |start: &gtk::TextIter, end: &gtk::TextIter| {
-            // ToDo: handle multiline formatting
-            let tag = b.tag_table().lookup(tag_str).unwrap();
-            b.begin_user_action();
-            if start.has_tag(&tag) {
-                b.remove_tag(&tag, start, end);
-            } else {
-                if COLORS.contains(&format) {
-                    for c in &COLORS {
-                        let tag = b.tag_table().lookup(Tag::from_char_format(c)).unwrap();
-                        b.remove_tag(&tag, start, end);
-                    }
-                }
-
-                b.apply_tag(&tag, start, end);
+        let cursor = b.get_insert_iter();
+        let link = b.get_link_at_iter(&cursor).or_else(|| b.get_image_at_iter(&cursor));
+        let link_bounds = link.and_then(|(_, tag)| b.get_current_tag_bounds(&tag));
+        let ranges = if let Some((mut start, mut end)) = b.selection_bounds() {
+            if end.starts_line() {
+                end.backward_char();
+            }
+            let lines = start.starts_line() && end.ends_line();
+            if format == CharFormat::Mono && lines {
+                b.apply_paragraph_format(Some(ParFormat::Code), &start, &end);
+                return;
+            } else if format == CharFormat::Math && lines {
+                b.apply_paragraph_format(Some(ParFormat::Math), &start, &end);
+                return;
             }
-            b.end_user_action();
+            self.extend_to_links(&mut start, &mut end);
+            self.char_format_ranges(&start, &end)
+        } else if let Some((start, end)) = link_bounds {
+            vec![(start.offset(), end.offset())]
+        } else if let Some((start, end)) = b.get_current_word_bounds() {
+            vec![(start.offset(), end.offset())]
+        } else {
+            self.tags.toggle_tag(tag_str);
+            return;
         };
 
-        // links should be formatted completely
-        // ToDo: a possible selection should be considered
-        let start = self.buffer.get_insert_iter();
-        if let Some((_, tag)) = self.buffer.get_link_at_iter(&start) {
-            if let Some((start, end)) = self.buffer.get_current_tag_bounds(&tag) {
-                toggle_tag(&start, &end);
-                return;
+        let tag = b.tag_table().lookup(tag_str).unwrap();
+        let formatted =
+            |&(start, end): &(i32, i32)| b.get_tag_ranges(&tag, start, end) == [(start, end)];
+        let remove = ranges.iter().all(formatted);
+        b.begin_user_action();
+        for (start, end) in ranges {
+            let (start, end) = (b.iter_at_offset(start), b.iter_at_offset(end));
+            if remove {
+                b.remove_tag(&tag, &start, &end);
+                continue;
             }
-        } else if let Some((_, tag)) = self.buffer.get_image_at_iter(&start) {
-            if let Some((start, end)) = self.buffer.get_current_tag_bounds(&tag) {
-                toggle_tag(&start, &end);
-                return;
+            if COLORS.contains(&format) {
+                for c in &COLORS {
+                    b.remove_tag_by_name(Tag::from_char_format(c), &start, &end);
+                }
             }
+            b.apply_tag(&tag, &start, &end);
         }
+        b.end_user_action();
+    }
 
-        if let Some((start, mut end)) = b.selection_bounds() {
-            if end.starts_line() {
-                end.backward_char();
+    // A selection starting or ending inside of a link or image is extended to all of it.
+    fn extend_to_links(&self, start: &mut gtk::TextIter, end: &mut gtk::TextIter) {
+        let is_link = |tag: &gtk::TextTag| tag.get_link().is_some() || tag.get_image().is_some();
+        for tag in start.tags().iter().filter(|tag| is_link(tag)) {
+            if !start.starts_tag(Some(tag)) {
+                start.backward_to_tag_toggle(Some(tag));
             }
-            if format == CharFormat::Mono && start.starts_line() && end.ends_line() {
-                b.apply_paragraph_format(Some(ParFormat::Code), &start, &end);
-            } else {
-                toggle_tag(&start, &end);
+        }
+        for tag in end.tags().iter().filter(|tag| is_link(tag)) {
+            if !end.starts_tag(Some(tag)) {
+                end.forward_to_tag_toggle(Some(tag));
+            }
+        }
+    }
+
+    // The offsets of the formatted parts of each line between start and end.
+    fn char_format_ranges(&self, start: &gtk::TextIter, end: &gtk::TextIter) -> Vec<(i32, i32)> {
+        let blocks: Vec<gtk::TextTag> = [Tag::CODE, Tag::MATH_BLOCK]
+            .iter()
+            .map(|name| self.buffer.tag_table().lookup(name).unwrap())
+            .collect();
+        let mut ranges = vec![];
+        let mut line_start = start.clone();
+        while line_start < *end {
+            let mut line_end = line_start.clone();
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            if line_end > *end {
+                line_end = end.clone();
+            }
+            if line_start < line_end && !blocks.iter().any(|tag| line_start.has_tag(tag)) {
+                ranges.push((line_start.offset(), line_end.offset()));
+            }
+            if !line_start.forward_line() {
+                break;
             }
-        } else if let Some((start, end)) = b.get_current_word_bounds() {
-            toggle_tag(&start, &end);
-        } else {
-            self.tags.toggle_tag(tag_str);
         }
+        ranges
     }
 
     pub fn apply_text_clear(&self) {
@@ -810,13 +1094,19 @@ impl TextView {
 
             let tag = if data.is_image {
                 buffer.create_image_tag(&data.link)
+            } else if data.reference.is_empty() {
+                buffer.create_link_tag(&data.link, None)
             } else {
-                buffer.create_link_tag(&data.link)
+                let label = markdown::escape_label(&data.reference);
+                buffer.create_link_tag(&data.link, Some(&label))
             };
             buffer.apply_tag(&tag, &start, &end);
 
             for tag in tags {
-                if tag.get_image().is_none() && tag.get_link().is_none() {
+                if tag.get_image().is_none()
+                    && tag.get_link().is_none()
+                    && tag.get_link_reference().is_none()
+                {
                     buffer.apply_tag(&tag, &start, &end);
                 }
             }
@@ -831,9 +1121,13 @@ impl TextView {
         let mut start = self.buffer.get_insert_iter();
         let mut end = start.clone();
         let mut link = String::new();
+        let mut reference = String::new();
         let mut is_image = false;
         if let Some((l, tag)) = self.buffer.get_link_at_iter(&start) {
             link = l;
+            if let Some((label, _)) = tag.get_link_reference() {
+                reference = markdown::unescape_label(&label);
+            }
             if !start.starts_tag(Some(&tag)) {
                 start.backward_to_tag_toggle(Some(&tag));
             }
@@ -873,12 +1167,43 @@ impl TextView {
         self.buffer.move_mark(&self.link_end, &end);
         let text = String::from(self.buffer.text(&start, &end, false).as_str());
 
-        let old_link = LinkData { text, link, is_image };
+        let old_link = LinkData { text, link, reference, is_image };
         self.search_bar.hide();
         self.link_edit.edit_link(&old_link);
         self.set_editable(false);
     }
 
+    pub fn edit_code_language(&self) {
+        if !self.is_editable() {
+            return;
+        }
+
+        let cursor = self.buffer.get_insert_iter();
+        if let Some((start, _)) = self.buffer.get_code_block_bounds(&cursor) {
+            let language = self.buffer.get_code_language_at_iter(&start).unwrap_or_default();
+            self.edt_code_language.set_text(&language);
+            self.search_bar.hide();
+            self.link_edit.reject();
+            self.code_language_bar.set_search_mode(true);
+            self.edt_code_language.grab_focus();
+        }
+    }
+
+    fn accept_code_language(&self) {
+        let cursor = self.buffer.get_insert_iter();
+        if let Some((start, end)) = self.buffer.get_code_block_bounds(&cursor) {
+            let language = self.edt_code_language.text();
+            self.buffer.set_code_language(language.as_str().trim(), &start, &end);
+        }
+        self.code_language_bar.set_search_mode(false);
+    }
+
+    fn on_code_language_bar(&self) {
+        if !self.code_language_bar.is_search_mode() {
+            self.grab_focus();
+        }
+    }
+
     pub fn open_search(&self) {
         if self.search_bar.is_open() {
             self.search_bar.hide();
@@ -892,37 +1217,71 @@ impl TextView {
         if !self.is_editable() {
             return;
         }
-        self.buffer.undo();
+        // the restored markdown of a converted rule stays text
+        self.without_input_rules(|| self.buffer.undo());
     }
 
     pub fn redo(&self) {
         if !self.is_editable() {
             return;
         }
-        self.buffer.redo();
+        self.without_input_rules(|| self.buffer.redo());
+    }
+
+    // Used for loading and saving from now on.
+    pub fn set_markdown_format(&self, format: Format) {
+        let source = self.source.borrow().as_ref().map(|source| source.with_format(&format));
+        self.source.replace(source);
+        self.format.replace(format);
     }
 
     pub fn to_markdown(&self) -> String {
-        self.buffer.to_markdown()
+        let markdown = self.buffer.to_markdown(&self.format.borrow());
+        match self.source.borrow().deref() {
+            Some(source) => source.restore(&markdown),
+            None => markdown,
+        }
+    }
+
+    pub fn to_html(&self, title: &str) -> String {
+        html::to_html(&self.buffer.to_document(), title)
+    }
+
+    pub fn print(
+        &self,
+        parent: &gtk::Window,
+        title: &str,
+        pdf: Option<&Path>,
+    ) -> Result<(), glib::Error> {
+        print::print(&self.buffer, parent, title, pdf)
     }
 
     pub fn clear(&self) {
         self.buffer.clear();
+        self.source.replace(None);
     }
 
     pub fn insert_markdown(&self, markdown: &str, clear: bool) {
         self.buffer.begin_user_action();
         if clear {
             self.buffer.clear();
+            self.source.replace(None);
         }
-        self.buffer.insert_markdown(&mut self.buffer.get_insert_iter(), markdown);
+        let format = self.format.borrow();
+        self.without_input_rules(|| {
+            self.buffer.insert_markdown(&mut self.buffer.get_insert_iter(), markdown, &format)
+        });
         self.buffer.end_user_action();
     }
 
     pub fn new_content_markdown(&self, markdown: &str) {
+        let format = self.format.borrow();
+        self.source.replace(Some(Source::new(markdown, &format)));
+        let suggesting = self.suggesting.replace(false);
         self.buffer.begin_irreversible_action();
-        self.buffer.assign_markdown(markdown, false);
+        self.without_input_rules(|| self.buffer.assign_markdown(markdown, &format, false));
         self.buffer.end_irreversible_action();
+        self.suggesting.replace(suggesting);
         self.buffer.place_cursor(&self.buffer.start_iter());
     }
 
@@ -933,10 +1292,14 @@ impl TextView {
     }
 
     fn turnaround(&self) {
+        let suggesting = self.suggesting.replace(false);
         self.buffer.begin_irreversible_action();
         let markdown = self.to_markdown();
-        self.buffer.assign_markdown(&markdown, true);
+        self.without_input_rules(|| {
+            self.buffer.assign_markdown(&markdown, &self.format.borrow(), true)
+        });
         self.buffer.end_irreversible_action();
+        self.suggesting.replace(suggesting);
         self.buffer.place_cursor(&self.buffer.start_iter());
     }
 
@@ -944,15 +1307,37 @@ impl TextView {
         if !self.is_editable() {
             return;
         }
+        // in front of the content of a list item the item is nested
+        if self.indent_list_item(true) {
+            return;
+        }
         let mut cursor = self.buffer.get_insert_iter();
         let remainder = cursor.line_offset() % TAB_WIDTH;
         self.buffer.insert(&mut cursor, &" ".repeat((4 - remainder) as usize));
     }
 
+    // A new line in the same paragraph of the markdown
+    fn insert_hard_break(&self) {
+        if !self.is_editable() {
+            return;
+        }
+        self.buffer.begin_user_action();
+        self.buffer.delete_selection(true, true);
+        let mut cursor = self.buffer.get_insert_iter();
+        let offset = cursor.offset();
+        self.without_input_rules(|| self.buffer.insert(&mut cursor, NEWLINE));
+        let start = self.buffer.iter_at_offset(offset);
+        self.buffer.apply_tag_by_name(Tag::HARD_BREAK, &start, &cursor);
+        self.buffer.end_user_action();
+    }
+
     fn remove_tab(&self) {
         if !self.is_editable() {
             return;
         }
+        if self.indent_list_item(false) {
+            return;
+        }
         let mut cursor = self.buffer.get_insert_iter();
         if !cursor.starts_line() {
             cursor.set_line(cursor.line());
@@ -973,7 +1358,214 @@ impl TextView {
         if !self.is_editable() {
             return;
         }
+        self.buffer.begin_user_action();
         self.buffer.text_move(up);
+        self.buffer.renumber_lists();
+        self.buffer.end_user_action();
+    }
+
+    // Changes the nesting of the list item at the cursor, if the cursor is in front of its content.
+    fn indent_list_item(&self, deeper: bool) -> bool {
+        let cursor = self.buffer.get_insert_iter();
+        match self.buffer.get_list_item(cursor.line()) {
+            Some((_, _, content))
+                if !self.buffer.has_selection() && cursor.line_offset() as usize <= content =>
+            {
+                self.nest_list_item(cursor.line(), deeper)
+            }
+            _ => false,
+        }
+    }
+
+    // The number of an ordered item starts again and is corrected by renumbering.
+    fn nest_list_item(&self, line: i32, deeper: bool) -> bool {
+        let (indent, number, _) = match self.buffer.get_list_item(line) {
+            Some(item) => item,
+            None => return false,
+        };
+        let indent = if deeper {
+            (indent / list::INDENT + 1) * list::INDENT
+        } else if indent > 0 {
+            (indent - 1) / list::INDENT * list::INDENT
+        } else {
+            return false;
+        };
+        self.buffer.begin_user_action();
+        self.buffer.replace_list_marker(line, indent, number.map(|_| 1));
+        self.buffer.renumber_lists();
+        self.buffer.end_user_action();
+        true
+    }
+
+    // Enter behind the marker of a list item starts the next item, in an empty item it ends the
+    // sublist or the list.
+    fn continue_list(&self) -> bool {
+        if !self.is_editable() || self.buffer.has_selection() {
+            return false;
+        }
+        let mut cursor = self.buffer.get_insert_iter();
+        let line = cursor.line();
+        let (indent, number, content) = match self.buffer.get_list_item(line) {
+            Some(item) => item,
+            None => return false,
+        };
+        if (cursor.line_offset() as usize) < content {
+            return false;
+        }
+        let mut start = self.buffer.iter_at_line(line).unwrap();
+        let mut end = cursor.clone();
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        let mut content_start = start.clone();
+        content_start.forward_chars(content as i32);
+        let text = self.buffer.text(&content_start, &end, false);
+        let is_task = |c: char| c == Tag::TASK_OPEN || c == Tag::TASK_DONE;
+        let task = text.starts_with(is_task);
+
+        self.buffer.begin_user_action();
+        if text.trim_start_matches(is_task).trim().is_empty() {
+            if indent > 0 {
+                self.nest_list_item(line, false);
+            } else {
+                self.buffer.set_list_level(line, None);
+                self.buffer.delete(&mut start, &mut end);
+            }
+        } else {
+            let marker = list::marker(indent, number.map(|number| number + 1));
+            self.buffer.insert(&mut cursor, format!("{}{}", NEWLINE, marker).as_str());
+            self.buffer.set_list_level(line + 1, Some(list::level(indent)));
+            if task {
+                self.buffer.toggle_task_in_line(line + 1);
+            }
+        }
+        self.buffer.renumber_lists();
+        self.buffer.end_user_action();
+        true
+    }
+
+    // The hidden front matter stays in the buffer and is saved.
+    pub fn toggle_front_matter(&self) {
+        let tag = self.buffer.tag_table().lookup(Tag::FRONT_MATTER).unwrap();
+        tag.set_invisible(!tag.is_invisible());
+    }
+
+    pub fn toggle_task(&self) {
+        if !self.is_editable() {
+            return;
+        }
+        self.buffer.toggle_task_in_line(self.buffer.get_insert_iter().line());
+    }
+
+    // Resolves the critic change under the cursor.
+    pub fn resolve_change(&self, accept: bool) {
+        if !self.is_editable() {
+            return;
+        }
+        if let Some((start, end)) = self.buffer.get_change_at_iter(&self.buffer.get_insert_iter()) {
+            self.buffer.resolve_changes(&start, &end, accept);
+        }
+    }
+
+    pub fn resolve_all_changes(&self, accept: bool) {
+        if !self.is_editable() {
+            return;
+        }
+        self.buffer.resolve_changes(&self.buffer.start_iter(), &self.buffer.end_iter(), accept);
+    }
+
+    // In suggestion mode the selection or the char at the cursor is marked as removal.
+    fn suggest_deletion(&self, forward: bool) {
+        if !self.is_editable() {
+            return;
+        }
+        let (start, end) = match self.buffer.selection_bounds() {
+            Some(bounds) => bounds,
+            None => {
+                let cursor = self.buffer.get_insert_iter();
+                let mut other = cursor.clone();
+                let moved = if forward {
+                    other.forward_cursor_position()
+                } else {
+                    other.backward_cursor_position()
+                };
+                if !moved {
+                    return;
+                }
+                if forward {
+                    (cursor, other)
+                } else {
+                    (other, cursor)
+                }
+            }
+        };
+        let (start, end) = self.buffer.mark_removal(&start, &end);
+        self.buffer.place_cursor(if forward { &end } else { &start });
+    }
+
+    pub fn goto_change(&self, backward: bool) {
+        if let Some(mut iter) = self.buffer.find_change(&self.buffer.get_insert_iter(), backward) {
+            self.buffer.place_cursor(&iter);
+            self.textview.scroll_to_iter(&mut iter, 0.05, false, 0., 0.);
+        }
+    }
+
+    // Every critic span with its kind, line, excerpt and offsets in the order of the document
+    pub fn get_review_model(&self) -> gtk::ListStore {
+        let model = gtk::ListStore::new(&[
+            glib::GString::static_type(),
+            glib::GString::static_type(),
+            glib::GString::static_type(),
+            glib::Type::I32,
+            glib::Type::I32,
+            glib::Type::I32,
+        ]);
+
+        let mut spans = vec![];
+        for name in &Tag::CRITIC {
+            let tag = self.buffer.tag_table().lookup(name).unwrap();
+            let end = self.buffer.end_iter().offset();
+            for (start, end) in self.buffer.get_tag_ranges(&tag, 0, end) {
+                spans.push((start, end, *name));
+            }
+        }
+        spans.sort_unstable();
+
+        for (start, end, name) in spans {
+            let kind = match name {
+                Tag::RED => "Removal",
+                Tag::GREEN => "Addition",
+                Tag::BLUE => "Comment",
+                _ => "Highlight",
+            };
+            let start_iter = self.buffer.iter_at_offset(start);
+            let text = self.buffer.text(&start_iter, &self.buffer.iter_at_offset(end), false);
+            let excerpt = text.lines().next().unwrap_or("").trim();
+            let excerpt: String = excerpt.chars().take(REVIEW_EXCERPT).collect();
+            let line = start_iter.line();
+            model.set(
+                &model.append(),
+                &[
+                    (0, &kind),
+                    (1, &(line + 1).to_string()),
+                    (2, &excerpt),
+                    (3, &line),
+                    (4, &start),
+                    (5, &end),
+                ],
+            );
+        }
+        model
+    }
+
+    pub fn select_range(&self, start: i32, end: i32) {
+        let start = self.buffer.iter_at_offset(start);
+        self.buffer.select_range(&start, &self.buffer.iter_at_offset(end));
+        self.grab_focus();
+    }
+
+    pub fn connect_changed<F: Fn(&gtk::TextBuffer) + 'static>(&self, f: F) {
+        self.buffer.connect_changed(f);
     }
 
     pub fn get_outline_model(&self, max_level: u32) -> gtk::ListStore {
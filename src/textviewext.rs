@@ -6,6 +6,7 @@ pub trait TextViewExt2 {
 
     fn get_image_at_location(&self, x: f64, y: f64) -> Option<String>;
     fn get_link_at_location(&self, x: f64, y: f64) -> Option<String>;
+    fn get_comment_at_location(&self, x: f64, y: f64) -> Option<String>;
 
     fn tooltip(&self, x: i32, y: i32, _keyboard_mode: bool, tooltip: &gtk::Tooltip) -> bool;
 }
@@ -28,6 +29,11 @@ impl TextViewExt2 for gtk::TextView {
         Some(name)
     }
 
+    fn get_comment_at_location(&self, x: f64, y: f64) -> Option<String> {
+        let iter = self.get_iter_at_coord(x, y)?;
+        self.buffer().get_highlight_comment_at_iter(&iter)
+    }
+
     fn tooltip(&self, x: i32, y: i32, _keyboard_mode: bool, tooltip: &gtk::Tooltip) -> bool {
         if let Some(link) = self.get_link_at_location(x as f64, y as f64) {
             tooltip.set_text(Some(link.as_str()));
@@ -35,6 +41,9 @@ impl TextViewExt2 for gtk::TextView {
         } else if let Some(image) = self.get_image_at_location(x as f64, y as f64) {
             tooltip.set_text(Some(image.as_str()));
             true
+        } else if let Some(comment) = self.get_comment_at_location(x as f64, y as f64) {
+            tooltip.set_text(Some(comment.as_str()));
+            true
         } else {
             false
         }